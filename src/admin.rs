@@ -0,0 +1,292 @@
+use dazeus::{DaZeusClient, Event, Scope};
+use config::Config;
+use handler::read_scope;
+use karma::{KarmaChange, KarmaValue};
+use filter::{self, FilterList};
+use store::{KarmaStore, Record, RepairReport};
+use alias;
+use identity;
+use term::normalize;
+use std::error::Error;
+
+/// The DaZeus permission that allows someone to manage karma.
+pub const ADMIN_PERMISSION: &'static str = "karma.admin";
+
+/// Check whether the sender of an event has the admin permission in DaZeus or
+/// is identified with services to one of the configured admin accounts.
+pub fn is_admin(evt: &Event, dazeus: &DaZeusClient, config: &Config) -> bool {
+    if dazeus.has_permission(ADMIN_PERMISSION, false, Scope::sender(&evt[0], &evt[2], &evt[1])) {
+        return true;
+    }
+    if config.admins.len() == 0 {
+        return false;
+    }
+
+    match identity::identified_account(dazeus, &evt[0], &evt[1]) {
+        Some(account) => config.admins.iter().any(|admin| normalize(admin) == account),
+        None => false,
+    }
+}
+
+/// Check that the sender of a command may manage karma, complaining if they can't.
+fn require_admin(evt: &Event, dazeus: &DaZeusClient, config: &Config) -> bool {
+    if is_admin(evt, dazeus, config) {
+        true
+    } else {
+        info!("Refused karma admin command '{}' from '{}' in '{}/{}'", &evt[3], &evt[1], &evt[0], &evt[2]);
+        dazeus.reply(&evt, "You're not allowed to do that", true);
+        false
+    }
+}
+
+pub fn reply_to_karmaalias_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
+    if !require_admin(evt, dazeus, config) {
+        return;
+    }
+
+    let scope = read_scope(evt, config);
+    match evt.len() {
+        6 => {
+            match alias::remove_alias(store, dazeus, scope, &evt[5]) {
+                Ok(true) => dazeus.reply(&evt, &format!("{} is no longer an alias", &evt[5])[..], true),
+                Ok(false) => dazeus.reply(&evt, &format!("{} isn't an alias", &evt[5])[..], true),
+                Err(e) => dazeus.reply(&evt, &format!("I couldn't remove the alias {}: {}", &evt[5], e)[..], true),
+            };
+        },
+        7 => {
+            let merged = match alias::merge_terms(store, dazeus, scope.clone(), config, &evt[5], &evt[6]) {
+                Ok(karma) => karma,
+                Err(e) => {
+                    dazeus.reply(&evt, &format!("I couldn't merge {} into {}: {}", &evt[5], &evt[6], e)[..], true);
+                    return;
+                }
+            };
+
+            match alias::set_alias(store, dazeus, scope, &evt[5], &merged.term[..]) {
+                Ok(_) => dazeus.reply(&evt, &format!("{} is now an alias for {}, which has a karma of {}", &evt[5], merged.term, merged.votes.to_string())[..], true),
+                Err(e) => dazeus.reply(&evt, &format!("I couldn't make {} an alias: {}", &evt[5], e)[..], true),
+            };
+        },
+        _ => {
+            dazeus.reply(&evt, "Use `karmaalias alias term` to add an alias, or `karmaalias alias` to remove one", true);
+        }
+    }
+}
+
+pub fn reply_to_karmamerge_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
+    if !require_admin(evt, dazeus, config) {
+        return;
+    }
+
+    if evt.len() != 7 {
+        dazeus.reply(&evt, "Use `karmamerge from into` to merge the karma of one term into another", true);
+        return;
+    }
+
+    match alias::merge_terms(store, dazeus, read_scope(evt, config), config, &evt[5], &evt[6]) {
+        Ok(karma) => dazeus.reply(&evt, &format!("Merged {} into {}, which now has a karma of {}", &evt[5], karma.term, karma.votes.to_string())[..], true),
+        Err(e) => dazeus.reply(&evt, &format!("I couldn't merge {} into {}: {}", &evt[5], &evt[6], e)[..], true),
+    };
+}
+
+pub fn reply_to_karmaset_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
+    if !require_admin(evt, dazeus, config) {
+        return;
+    }
+
+    let votes = if evt.len() == 8 {
+        match (evt[6].parse::<u64>(), evt[7].parse::<u64>()) {
+            (Ok(up), Ok(down)) => Some(KarmaChange::new(up, down)),
+            _ => None,
+        }
+    } else {
+        None
+    };
+
+    match votes {
+        Some(votes) => {
+            reply_with_update(evt, dazeus, config, store, votes);
+        },
+        None => {
+            dazeus.reply(&evt, "Use `karmaset term up down` to set the number of upvotes and downvotes of a term", true);
+        }
+    }
+}
+
+pub fn reply_to_karmareset_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
+    if !require_admin(evt, dazeus, config) {
+        return;
+    }
+
+    if evt.len() != 6 {
+        dazeus.reply(&evt, "Which term do you want to reset?", true);
+        return;
+    }
+
+    reply_with_update(evt, dazeus, config, store, KarmaChange::new(0, 0));
+}
+
+pub fn reply_to_karmadelete_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
+    if !require_admin(evt, dazeus, config) {
+        return;
+    }
+
+    if evt.len() != 6 {
+        dazeus.reply(&evt, "Which term do you want to delete?", true);
+        return;
+    }
+
+    let scope = read_scope(evt, config);
+    let term = alias::resolve(store, dazeus, scope.clone(), &evt[5]);
+    match store.delete(dazeus, scope.clone(), &term[..]) {
+        Ok(true) => (),
+        Ok(false) => {
+            dazeus.reply(&evt, &format!("There is no karma for {}", term)[..], true);
+            return;
+        },
+        Err(e) => {
+            warn!("Could not delete the karma of '{}' in '{}': {}", term, &evt[0], e);
+            dazeus.reply(&evt, &format!("I couldn't delete the karma of {}", term)[..], true);
+            return;
+        }
+    }
+
+    for record in [Record::Trend, Record::Reasons].iter() {
+        if let Err(e) = store.put_record(dazeus, scope.clone(), *record, &term[..], None) {
+            warn!("Could not delete the {} of '{}' in '{}': {}", record.to_str(), term, &evt[0], e);
+        }
+    }
+    info!("'{}' deleted the karma of '{}' in '{}/{}'", &evt[1], term, &evt[0], &evt[2]);
+    dazeus.reply(&evt, &format!("Deleted all karma of {}", term)[..], true);
+}
+
+pub fn reply_to_karmafsck_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
+    if !require_admin(evt, dazeus, config) {
+        return;
+    }
+
+    match store.repair(dazeus, read_scope(evt, config)) {
+        Ok(RepairReport { checked, repaired: 0 }) => {
+            dazeus.reply(&evt, &format!("Checked {} terms, everything is fine", checked)[..], true);
+        },
+        Ok(RepairReport { checked, repaired }) => {
+            info!("'{}' repaired {} corrupt records in '{}/{}'", &evt[1], repaired, &evt[0], &evt[2]);
+            dazeus.reply(&evt, &format!("Checked {} terms and repaired {} corrupt records", checked, repaired)[..], true);
+        },
+        Err(e) => {
+            warn!("Could not check the stored karma in '{}/{}': {}", &evt[0], &evt[2], e);
+            dazeus.reply(&evt, &format!("I couldn't check the stored karma: {}", e)[..], true);
+        }
+    }
+}
+
+pub fn reply_to_karmadeny_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
+    manage_filter(evt, dazeus, config, store, FilterList::Deny);
+}
+
+pub fn reply_to_karmaallow_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
+    manage_filter(evt, dazeus, config, store, FilterList::Allow);
+}
+
+/// List, add to or remove from one of the term filter lists.
+fn manage_filter(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore, list: FilterList) {
+    let scope = read_scope(evt, config);
+    if evt.len() == 5 {
+        let configured = match list {
+            FilterList::Deny => &config.deny,
+            FilterList::Allow => &config.allow,
+        };
+        let patterns = configured.iter().cloned()
+            .chain(filter::stored_patterns(store, dazeus, scope, list).into_iter())
+            .collect::<Vec<String>>();
+        if patterns.len() == 0 {
+            dazeus.reply(&evt, &format!("The {} list is empty", list.to_str())[..], true);
+        } else {
+            dazeus.reply(&evt, &format!("On the {} list: {}", list.to_str(), patterns.connect(", "))[..], true);
+        }
+        return;
+    }
+
+    if evt.len() != 7 || (&evt[5] != "add" && &evt[5] != "remove") {
+        let command = format!("karma{}", list.to_str());
+        dazeus.reply(&evt, &format!("Use `{} add term`, `{} remove term` or just `{}` to see the list, use /regex/ to match a pattern", command, command, command)[..], true);
+        return;
+    }
+
+    if !require_admin(evt, dazeus, config) {
+        return;
+    }
+
+    if &evt[5] == "add" {
+        match filter::add_pattern(store, dazeus, scope, list, &evt[6]) {
+            Ok(pattern) => {
+                info!("'{}' added '{}' to the {} list in '{}/{}'", &evt[1], pattern, list.to_str(), &evt[0], &evt[2]);
+                dazeus.reply(&evt, &format!("Added {} to the {} list", pattern, list.to_str())[..], true)
+            },
+            Err(e) => dazeus.reply(&evt, &format!("I couldn't add {} to the {} list: {}", &evt[6], list.to_str(), e)[..], true),
+        };
+    } else {
+        match filter::remove_pattern(store, dazeus, scope, list, &evt[6]) {
+            Ok(true) => {
+                info!("'{}' removed '{}' from the {} list in '{}/{}'", &evt[1], &evt[6], list.to_str(), &evt[0], &evt[2]);
+                dazeus.reply(&evt, &format!("Removed {} from the {} list", &evt[6], list.to_str())[..], true)
+            },
+            Ok(false) => dazeus.reply(&evt, &format!("{} isn't on the {} list, or is only in the configuration", &evt[6], list.to_str())[..], true),
+            Err(e) => dazeus.reply(&evt, &format!("I couldn't remove {} from the {} list: {}", &evt[6], list.to_str(), e)[..], true),
+        };
+    }
+}
+
+fn reply_with_update(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore, votes: KarmaChange) {
+    match update_karma(evt, dazeus, config, store, &evt[5], votes) {
+        Ok(karma) => dazeus.reply(&evt, &karma.to_string()[..], true),
+        Err(e) => {
+            warn!("Could not change the karma of '{}' in '{}': {}", &evt[5], &evt[0], e);
+            dazeus.reply(&evt, &format!("I couldn't change the karma of {}", &evt[5])[..], true)
+        }
+    };
+}
+
+/// Overwrite the votes of a term, leaving the rest of its karma as it was.
+fn update_karma(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore, term: &str, votes: KarmaChange) -> Result<KarmaValue, Box<Error>> {
+    let scope = read_scope(evt, config);
+    let mut karma = match try!(KarmaValue::load(store, dazeus, scope.clone(), config, term)) {
+        Some(karma) => karma,
+        _ => KarmaValue::new(&alias::resolve(store, dazeus, scope.clone(), term)[..]),
+    };
+
+    info!("'{}' changed the karma of '{}' in '{}/{}' from {} to {}", &evt[1], karma.term, &evt[0], &evt[2], karma.votes.to_string(), votes.to_string());
+    karma.votes = votes;
+    try!(store.put(dazeus, scope, &karma));
+    Ok(karma)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_admin;
+    use config::Config;
+    use fake::FakeDaZeus;
+
+    #[test]
+    fn admins_are_identified_to_their_account() {
+        let mut dazeus = FakeDaZeus::new();
+        let mut config = Config::new();
+        config.admins = vec!("Ruben".to_string());
+
+        // anyone can take the nick
+        assert!(!is_admin(&dazeus.command("ruben", "karmaset", "foo 1 0"), &dazeus, &config));
+
+        dazeus.accounts.push(("ruben_".to_string(), "ruben".to_string()));
+        dazeus.accounts.push(("ruben".to_string(), "mallory".to_string()));
+        assert!(is_admin(&dazeus.command("ruben_", "karmaset", "foo 1 0"), &dazeus, &config));
+        assert!(!is_admin(&dazeus.command("ruben", "karmaset", "foo 1 0"), &dazeus, &config));
+    }
+
+    #[test]
+    fn the_dazeus_permission_is_enough() {
+        let mut dazeus = FakeDaZeus::new();
+        dazeus.admins.push("alice".to_string());
+        assert!(is_admin(&dazeus.command("alice", "karmaset", "foo 1 0"), &dazeus, &Config::new()));
+        assert!(!is_admin(&dazeus.command("bob", "karmaset", "foo 1 0"), &dazeus, &Config::new()));
+    }
+}
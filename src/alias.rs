@@ -0,0 +1,104 @@
+use dazeus::{DaZeusClient, Scope};
+use config::Config;
+use error::KarmaError;
+use karma::KarmaValue;
+use reason::Reasons;
+use store::{KarmaStore, Record};
+use term::normalize;
+use trend::Trend;
+use std::error::Error;
+
+/// Aliases may point to other aliases, but we stop following them at some point.
+const MAX_ALIAS_DEPTH: usize = 10;
+
+/// Find the term that votes for `term` should be counted towards.
+pub fn resolve(store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope, term: &str) -> String {
+    let mut current = normalize(term);
+    for _ in 0..MAX_ALIAS_DEPTH {
+        match get_alias(store, dazeus, scope.clone(), &current[..]) {
+            Some(target) => current = target,
+            None => return current,
+        }
+    }
+
+    warn!("Giving up on resolving aliases for '{}', the chain is too long", term);
+    current
+}
+
+fn get_alias(store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope, term: &str) -> Option<String> {
+    match store.get_record(dazeus, scope, Record::Alias, term) {
+        Ok(Some(ref s)) if s.trim() != "" => Some(normalize(s)),
+        Ok(_) => None,
+        Err(e) => {
+            warn!("Could not look up whether '{}' is an alias: {}", term, e);
+            None
+        }
+    }
+}
+
+/// Redirect all future votes for `alias` to `target`.
+pub fn set_alias(store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope, alias: &str, target: &str) -> Result<(), Box<Error>> {
+    let alias = normalize(alias);
+    if resolve(store, dazeus, scope.clone(), target) == alias {
+        return Err(From::from(KarmaError::new(&format!("{} already refers to {}", target, alias)[..])));
+    }
+
+    store.put_record(dazeus, scope, Record::Alias, &alias[..], Some(&normalize(target)[..]))
+}
+
+pub fn remove_alias(store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope, alias: &str) -> Result<bool, Box<Error>> {
+    let alias = normalize(alias);
+    if get_alias(store, dazeus, scope.clone(), &alias[..]).is_none() {
+        return Ok(false);
+    }
+
+    try!(store.put_record(dazeus, scope, Record::Alias, &alias[..], None));
+    Ok(true)
+}
+
+/// Fold all karma, vote history, trend and reasons of `from` into `into` and remove `from`.
+pub fn merge_terms(store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope, config: &Config, from: &str, into: &str) -> Result<KarmaValue, Box<Error>> {
+    let from = normalize(from);
+    let into = resolve(store, dazeus, scope.clone(), into);
+    if from == into {
+        return Err(From::from(KarmaError::new("Can't merge a term into itself")));
+    }
+
+    let source = match try!(store.get(dazeus, scope.clone(), &from[..])) {
+        Some(karma) => karma,
+        None => KarmaValue::new(&from[..]),
+    };
+    let source_history = try!(store.history(dazeus, scope.clone(), &from[..]));
+
+    let mut target = match try!(KarmaValue::load(store, dazeus, scope.clone(), config, &into[..])) {
+        Some(karma) => karma,
+        None => KarmaValue::new(&into[..]),
+    };
+    target.merge(&source);
+    try!(store.put(dazeus, scope.clone(), &target));
+
+    if source_history.votes.len() > 0 {
+        let mut target_history = try!(store.history(dazeus, scope.clone(), &into[..]));
+        target_history.merge(&source_history);
+        try!(store.put_history(dazeus, scope.clone(), &target_history));
+    }
+    try!(store.delete(dazeus, scope.clone(), &from[..]));
+
+    let source_trend = try!(Trend::load(store, dazeus, scope.clone(), &from[..]));
+    if source_trend.days.len() > 0 {
+        let mut target_trend = try!(Trend::load(store, dazeus, scope.clone(), &into[..]));
+        target_trend.merge(&source_trend);
+        try!(target_trend.save(store, dazeus, scope.clone()));
+        try!(store.put_record(dazeus, scope.clone(), Record::Trend, &from[..], None));
+    }
+
+    let source_reasons = try!(Reasons::load(store, dazeus, scope.clone(), &from[..]));
+    if source_reasons.reasons.len() > 0 {
+        let mut target_reasons = try!(Reasons::load(store, dazeus, scope.clone(), &into[..]));
+        target_reasons.merge(&source_reasons);
+        try!(target_reasons.save(store, dazeus, scope.clone()));
+        try!(store.put_record(dazeus, scope, Record::Reasons, &from[..], None));
+    }
+
+    Ok(target)
+}
@@ -0,0 +1,400 @@
+use dazeus::{DaZeusClient, ConfigGroup, Scope};
+use error::KarmaError;
+use karma::{KarmaOperator, KarmaStyle, STORE_PREFIX};
+use term::normalize;
+use std::ascii::AsciiExt;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use toml;
+
+/// What to do when someone votes on their own nick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelfKarma {
+    Allow,
+    Reject,
+    Invert
+}
+
+impl SelfKarma {
+    pub fn from_str(s: &str) -> Option<SelfKarma> {
+        match &s.trim().to_ascii_lowercase()[..] {
+            "allow" => Some(SelfKarma::Allow),
+            "reject" => Some(SelfKarma::Reject),
+            "invert" => Some(SelfKarma::Invert),
+            _ => None,
+        }
+    }
+}
+
+/// Where karma is kept: one pool for everything, one per network or one per
+/// channel. With `ChannelRollup` karma is kept per channel, but every vote is
+/// also counted towards the karma of the whole network.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScopeMode {
+    Global,
+    Network,
+    Channel,
+    ChannelRollup
+}
+
+impl ScopeMode {
+    pub fn from_str(s: &str) -> Option<ScopeMode> {
+        match &s.trim().to_ascii_lowercase()[..] {
+            "global" => Some(ScopeMode::Global),
+            "network" => Some(ScopeMode::Network),
+            "channel" => Some(ScopeMode::Channel),
+            "rollup" => Some(ScopeMode::ChannelRollup),
+            _ => None,
+        }
+    }
+
+    /// The scope karma should be read from for a message in the given channel.
+    pub fn read_scope(&self, network: &str, channel: &str) -> Scope {
+        match *self {
+            ScopeMode::Global => Scope::any(),
+            ScopeMode::Network => Scope::network(network),
+            ScopeMode::Channel | ScopeMode::ChannelRollup => Scope::target(network, channel),
+        }
+    }
+
+    /// All scopes a vote in the given channel should be stored in.
+    pub fn write_scopes(&self, network: &str, channel: &str) -> Vec<Scope> {
+        match *self {
+            ScopeMode::ChannelRollup => vec!(Scope::target(network, channel), Scope::network(network)),
+            _ => vec!(self.read_scope(network, channel)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub store_prefix: String,
+    pub scope: ScopeMode,
+    pub syntaxes: Vec<KarmaStyle>,
+    pub operators: Vec<KarmaOperator>,
+    pub ignore_quotes: bool,
+    pub max_change: u64,
+    pub highlight_char: String,
+    pub nick: String,
+    pub notify_message: String,
+    pub self_karma: SelfKarma,
+    pub self_karma_message: Option<String>,
+    pub nick_aliases: Vec<(String, Vec<String>)>,
+    pub admins: Vec<String>,
+    pub deny: Vec<String>,
+    pub allow: Vec<String>,
+    pub disabled_votes: Vec<String>,
+    pub disabled_notify: Vec<String>,
+    pub disabled_commands: Vec<String>,
+    pub identities: bool,
+    pub half_life: f64,
+    pub term_cooldown: u64,
+    pub term_burst: usize,
+    pub voter_cooldown: u64,
+    pub voter_burst: usize,
+    pub rate_limit_warning: bool,
+    pub rate_limit_message: String
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            store_prefix: STORE_PREFIX.to_string(),
+            scope: ScopeMode::Network,
+            syntaxes: vec!(KarmaStyle::Notify, KarmaStyle::Silent, KarmaStyle::Implicit),
+            operators: vec!(KarmaOperator::Postfix, KarmaOperator::Vote, KarmaOperator::Assign),
+            ignore_quotes: false,
+            max_change: 5,
+            highlight_char: "}".to_string(),
+            nick: "DaZeus".to_string(),
+            notify_message: "{voter} {direction} the karma of {term} to {karma}".to_string(),
+            self_karma: SelfKarma::Reject,
+            self_karma_message: None,
+            nick_aliases: Vec::new(),
+            admins: Vec::new(),
+            deny: Vec::new(),
+            allow: Vec::new(),
+            disabled_votes: Vec::new(),
+            disabled_notify: Vec::new(),
+            disabled_commands: Vec::new(),
+            identities: false,
+            half_life: 0.0,
+            term_cooldown: 300,
+            term_burst: 2,
+            voter_cooldown: 60,
+            voter_burst: 10,
+            rate_limit_warning: true,
+            rate_limit_message: "You're handing out karma a bit too quickly, I'll ignore your votes for a while".to_string()
+        }
+    }
+
+    /// Read the plugin configuration as provided by the DaZeus core and, if
+    /// given, the configuration file at `path`. Settings in the file take
+    /// precedence over those from the core, the defaults are used for anything
+    /// that isn't configured at all.
+    pub fn load(dazeus: &DaZeusClient, path: Option<&str>) -> Result<Config, Box<Error>> {
+        let mut config = Config::new();
+        config.apply(|name| get_plugin_config(dazeus, name));
+
+        if let Some(path) = path {
+            let table = try!(read_config_file(path));
+            config.apply(|name| table.get(name).and_then(toml_to_config_str));
+        }
+
+        Ok(config)
+    }
+
+    /// Update the configuration with all settings that `get` has a value for.
+    fn apply<F>(&mut self, get: F) where F: Fn(&str) -> Option<String> {
+        macro_rules! parse_config {
+            ($name:expr, $field:expr) => (if let Some(s) = get($name) {
+                match s.trim().parse() {
+                    Ok(v) => $field = v,
+                    Err(_) => warn!("Ignoring invalid value '{}' for {}", s, $name),
+                }
+            });
+            ($name:expr, $field:expr, $parse:expr) => (if let Some(s) = get($name) {
+                match $parse(&s[..]) {
+                    Some(v) => $field = v,
+                    None => warn!("Ignoring invalid value '{}' for {}", s, $name),
+                }
+            });
+        }
+
+        parse_config!("store_prefix", self.store_prefix);
+        parse_config!("scope", self.scope, ScopeMode::from_str);
+        parse_config!("syntaxes", self.syntaxes, parse_syntaxes);
+        parse_config!("operators", self.operators, parse_operators);
+        parse_config!("ignore_quotes", self.ignore_quotes);
+        parse_config!("max_change", self.max_change);
+        parse_config!("highlight_char", self.highlight_char);
+        parse_config!("nick", self.nick);
+        parse_config!("notify_message", self.notify_message);
+        parse_config!("self_karma", self.self_karma, SelfKarma::from_str);
+        parse_config!("nick_aliases", self.nick_aliases, |s| Some(parse_nick_aliases(s)));
+        parse_config!("admins", self.admins, parse_admins);
+        parse_config!("deny", self.deny, parse_list);
+        parse_config!("allow", self.allow, parse_list);
+        parse_config!("disabled_votes", self.disabled_votes, parse_list);
+        parse_config!("disabled_notify", self.disabled_notify, parse_list);
+        parse_config!("disabled_commands", self.disabled_commands, parse_list);
+        parse_config!("identities", self.identities);
+        parse_config!("half_life", self.half_life);
+        parse_config!("term_cooldown", self.term_cooldown);
+        parse_config!("term_burst", self.term_burst);
+        parse_config!("voter_cooldown", self.voter_cooldown);
+        parse_config!("voter_burst", self.voter_burst);
+        parse_config!("rate_limit_warning", self.rate_limit_warning);
+        parse_config!("rate_limit_message", self.rate_limit_message);
+
+        if let Some(s) = get("self_karma_message") {
+            self.self_karma_message = Some(s);
+        }
+
+        // other properties are stored next to the karma, keep them apart
+        if !self.store_prefix.ends_with(".") {
+            self.store_prefix.push('.');
+        }
+    }
+
+    /// The prefix of the properties the vote history of each term is stored in.
+    pub fn history_prefix(&self) -> String {
+        format!("{}_history.", self.store_prefix.trim_right_matches('.'))
+    }
+
+    /// The prefix of the properties in which every plugin instance counts the
+    /// votes it stored for each term.
+    pub fn tally_prefix(&self) -> String {
+        format!("{}_tally.", self.store_prefix.trim_right_matches('.'))
+    }
+
+    /// The prefix of the properties in which every plugin instance keeps the
+    /// votes it added to the history of each term.
+    pub fn votes_prefix(&self) -> String {
+        format!("{}_votes.", self.store_prefix.trim_right_matches('.'))
+    }
+
+    /// The prefix of the properties that keep the number of votes per day.
+    pub fn trend_prefix(&self) -> String {
+        format!("{}_trend.", self.store_prefix.trim_right_matches('.'))
+    }
+
+    /// The prefix of the properties that redirect one term to another.
+    pub fn alias_prefix(&self) -> String {
+        format!("{}_alias.", self.store_prefix.trim_right_matches('.'))
+    }
+
+    /// The prefix of the properties that keep the reasons given for votes.
+    pub fn reasons_prefix(&self) -> String {
+        format!("{}_reasons.", self.store_prefix.trim_right_matches('.'))
+    }
+
+    /// The prefix of the properties that keep stored karma we couldn't read.
+    pub fn quarantine_prefix(&self) -> String {
+        format!("{}_quarantine.", self.store_prefix.trim_right_matches('.'))
+    }
+
+    /// The prefix of the properties that map nicks to the person using them.
+    pub fn identity_prefix(&self) -> String {
+        format!("{}_identity.", self.store_prefix.trim_right_matches('.'))
+    }
+
+    /// The prefix of the properties that keep the deny and allow lists.
+    pub fn filter_prefix(&self) -> String {
+        format!("{}_filter.", self.store_prefix.trim_right_matches('.'))
+    }
+
+    /// The property that lists the channels of a network karma was stored in.
+    pub fn channels_property(&self) -> String {
+        format!("{}_channels", self.store_prefix.trim_right_matches('.'))
+    }
+
+    /// The prefix of the properties that turn parts of the plugin on or off.
+    pub fn switch_prefix(&self) -> String {
+        format!("{}_switch.", self.store_prefix.trim_right_matches('.'))
+    }
+
+    /// Check whether the given term refers to the given nick or one of its aliases.
+    pub fn is_nick(&self, nick: &str, term: &str) -> bool {
+        let nick = normalize(nick);
+        let term = normalize(term);
+        if nick == term {
+            return true;
+        }
+
+        self.nick_aliases.iter()
+            .filter(|&&(ref n, _)| *n == nick)
+            .any(|&(_, ref aliases)| aliases.iter().any(|a| *a == term))
+    }
+}
+
+/// Fill in a message template, replacing every `{name}` with its value.
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut result = template.to_string();
+    for &(name, value) in values {
+        result = result.replace(&format!("{{{}}}", name)[..], value);
+    }
+    result
+}
+
+fn read_config_file(path: &str) -> Result<toml::Table, Box<Error>> {
+    let mut contents = String::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_string(&mut contents)));
+
+    let mut parser = toml::Parser::new(&contents[..]);
+    match parser.parse() {
+        Some(table) => Ok(table),
+        None => {
+            let errors = parser.errors.iter().map(|e| {
+                let (line, col) = parser.to_linecol(e.lo);
+                format!("{}:{}: {}", line + 1, col + 1, e.desc)
+            }).collect::<Vec<String>>().connect(", ");
+            Err(From::from(KarmaError::new(&format!("Invalid configuration file '{}': {}", path, errors)[..])))
+        }
+    }
+}
+
+/// Convert a value from the configuration file into the same form the DaZeus
+/// core would give us: lists are separated by `,` and tables are written as
+/// `key=value;key=value`.
+fn toml_to_config_str(value: &toml::Value) -> Option<String> {
+    match *value {
+        toml::Value::String(ref s) => Some(s.clone()),
+        toml::Value::Integer(i) => Some(i.to_string()),
+        toml::Value::Float(f) => Some(f.to_string()),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        toml::Value::Datetime(ref s) => Some(s.clone()),
+        toml::Value::Array(ref a) => Some(a.iter().filter_map(toml_to_config_str).collect::<Vec<String>>().connect(",")),
+        toml::Value::Table(ref t) => Some(t.iter().filter_map(|(k, v)| {
+            toml_to_config_str(v).map(|v| format!("{}={}", k, v))
+        }).collect::<Vec<String>>().connect(";")),
+    }
+}
+
+/// Parses a comma separated list of names or patterns.
+fn parse_list(s: &str) -> Option<Vec<String>> {
+    Some(s.split(',').map(|a| a.trim().to_string()).filter(|a| a.len() > 0).collect())
+}
+
+/// Parses the list of admin accounts. Anyone can take a nick, so admins are
+/// the services accounts people identify to. DaZeus doesn't tell us the
+/// hostmask of a sender, so hostmasks can't be checked, and wildcards would
+/// let anyone register a matching account; both are left out.
+fn parse_admins(s: &str) -> Option<Vec<String>> {
+    parse_list(s).map(|admins| admins.into_iter().filter(|admin| {
+        if admin.contains('!') || admin.contains('@') {
+            warn!("Ignoring admin '{}', hostmasks can't be checked, use the services account instead", admin);
+            false
+        } else if admin.contains('*') || admin.contains('?') {
+            warn!("Ignoring admin '{}', admins are services accounts and can't have wildcards", admin);
+            false
+        } else {
+            true
+        }
+    }).collect())
+}
+
+/// Parses a list of karma syntaxes like `notify,silent`.
+fn parse_syntaxes(s: &str) -> Option<Vec<KarmaStyle>> {
+    let mut result = Vec::new();
+    for name in s.split(',').map(|n| n.trim()).filter(|n| n.len() > 0) {
+        match KarmaStyle::from_str(&name.to_ascii_lowercase()[..]) {
+            Some(style) => result.push(style),
+            None => return None,
+        }
+    }
+    Some(result)
+}
+
+fn get_plugin_config(dazeus: &DaZeusClient, name: &str) -> Option<String> {
+    match dazeus.get_config(name, ConfigGroup::Plugin).get_str("value") {
+        Some(s) if s.trim() != "" => Some(s.to_string()),
+        _ => None,
+    }
+}
+
+/// Parses a list of karma operators like `postfix,vote`.
+fn parse_operators(s: &str) -> Option<Vec<KarmaOperator>> {
+    let mut result = Vec::new();
+    for name in s.split(',').map(|n| n.trim()).filter(|n| n.len() > 0) {
+        match KarmaOperator::from_str(&name.to_ascii_lowercase()[..]) {
+            Some(operator) => result.push(operator),
+            None => return None,
+        }
+    }
+    Some(result)
+}
+
+/// Parses aliases in the form `nick=alias,alias;othernick=alias`.
+fn parse_nick_aliases(s: &str) -> Vec<(String, Vec<String>)> {
+    let mut result = Vec::new();
+    for entry in s.split(';') {
+        let mut parts = entry.splitn(2, '=');
+        let nick = normalize(parts.next().unwrap_or(""));
+        let aliases = parts.next().unwrap_or("")
+            .split(',')
+            .map(|a| normalize(a))
+            .filter(|a| a.len() > 0)
+            .collect::<Vec<String>>();
+
+        if nick.len() > 0 && aliases.len() > 0 {
+            result.push((nick, aliases));
+        } else if entry.trim().len() > 0 {
+            warn!("Ignoring invalid nick alias entry '{}'", entry);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    #[test]
+    fn only_accounts_are_admins() {
+        let mut config = Config::new();
+        config.apply(|name| if name == "admins" { Some("ruben, *!*@staff.example.org, bob@host, a*, rub?n".to_string()) } else { None });
+        assert_eq!(config.admins, vec!("ruben".to_string()));
+    }
+}
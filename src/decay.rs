@@ -0,0 +1,129 @@
+use chrono::{DateTime, Local};
+use dazeus::{DaZeusClient, Scope};
+use config::Config;
+use history::{VoteHistory, MAX_VOTES};
+use karma::KarmaValue;
+use store::KarmaStore;
+
+const SECONDS_PER_DAY: f64 = 86400.0;
+
+/// Fill in the current (decayed) karma of a term, if decay is enabled.
+pub fn apply_decay(karma: &mut KarmaValue, store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope, config: &Config) {
+    if config.half_life <= 0.0 {
+        return;
+    }
+
+    let history = match store.history(dazeus, scope, &karma.term[..]) {
+        Ok(history) => history,
+        Err(e) => {
+            warn!("Could not read vote history of '{}', not applying decay: {}", karma.term, e);
+            VoteHistory::new(&karma.term[..])
+        }
+    };
+    karma.current = Some(decayed_karma(karma, &history, config.half_life, Local::now()));
+}
+
+/// Calculate the karma of a term where every vote loses half of its weight each
+/// `half_life` days. Votes from before we kept a history are assumed to have
+/// been cast at the time of the last vote, unless the history is full: then the
+/// votes that didn't fit are older than any vote it still has. When there are
+/// fewer votes than the history has, as after `}karmaset` or `}karmareset`, the
+/// votes in the history are scaled down to match.
+pub fn decayed_karma(karma: &KarmaValue, history: &VoteHistory, half_life: f64, now: DateTime<Local>) -> f64 {
+    let weight = |time: DateTime<Local>| -> f64 {
+        let age = (now - time).num_seconds() as f64 / SECONDS_PER_DAY;
+        if age <= 0.0 { 1.0 } else { (0.5f64).powf(age / half_life) }
+    };
+
+    let mut up = 0.0;
+    let mut down = 0.0;
+    let mut logged_up = 0;
+    let mut logged_down = 0;
+    for vote in history.votes.iter() {
+        up += vote.change.up as f64 * weight(vote.time);
+        down += vote.change.down as f64 * weight(vote.time);
+        logged_up += vote.change.up;
+        logged_down += vote.change.down;
+    }
+
+    let unlogged_time = match history.votes.first() {
+        Some(oldest) if history.votes.len() >= MAX_VOTES => oldest.time,
+        _ => karma.last_vote,
+    };
+    let matching = |decayed: f64, logged: u64, stored: u64| -> f64 {
+        if logged > stored {
+            decayed * stored as f64 / logged as f64
+        } else {
+            decayed + (stored - logged) as f64 * weight(unlogged_time)
+        }
+    };
+    matching(up, logged_up, karma.votes.up) - matching(down, logged_down, karma.votes.down)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Local};
+    use history::{Vote, VoteHistory, MAX_VOTES};
+    use karma::{Karma, KarmaChange, KarmaOperator, KarmaStyle, KarmaValue};
+
+    const HALF_LIFE: f64 = 30.0;
+
+    fn history(votes: &[(u64, u64, i64)]) -> VoteHistory {
+        let mut history = VoteHistory::new("foo");
+        for &(up, down, days_ago) in votes.iter() {
+            let mut vote = Vote::new("alice", "#karma", &Karma {
+                term: "foo".to_string(),
+                change: KarmaChange::new(up, down),
+                operator: KarmaOperator::Postfix,
+                style: KarmaStyle::Implicit,
+                reason: None
+            });
+            vote.time = Local::now() - Duration::days(days_ago);
+            history.votes.push(vote);
+        }
+        history
+    }
+
+    fn karma(up: u64, down: u64) -> KarmaValue {
+        let mut karma = KarmaValue::new("foo");
+        karma.votes = KarmaChange::new(up, down);
+        karma
+    }
+
+    fn round(n: f64) -> f64 {
+        (n * 100.0).round() / 100.0
+    }
+
+    #[test]
+    fn votes_lose_half_their_weight_every_half_life() {
+        let history = history(&[(1, 0, 0), (1, 0, 30), (0, 1, 60)]);
+        assert_eq!(round(decayed_karma(&karma(2, 1), &history, HALF_LIFE, Local::now())), 1.25);
+    }
+
+    #[test]
+    fn votes_without_history_count_from_the_last_vote() {
+        let history = history(&[(1, 0, 30)]);
+        assert_eq!(round(decayed_karma(&karma(3, 0), &history, HALF_LIFE, Local::now())), 2.5);
+    }
+
+    #[test]
+    fn reset_karma_has_no_current_karma() {
+        let history = history(&[(1, 0, 0), (1, 0, 1), (0, 1, 2)]);
+        assert_eq!(decayed_karma(&karma(0, 0), &history, HALF_LIFE, Local::now()), 0.0);
+    }
+
+    #[test]
+    fn set_karma_scales_the_history() {
+        let history = history(&[(1, 0, 0), (1, 0, 0), (1, 0, 0), (1, 0, 0), (0, 1, 0)]);
+        assert_eq!(round(decayed_karma(&karma(2, 0), &history, HALF_LIFE, Local::now())), 2.0);
+        assert_eq!(round(decayed_karma(&karma(2, 3), &history, HALF_LIFE, Local::now())), -1.0);
+    }
+
+    #[test]
+    fn votes_dropped_from_a_full_history_are_older_than_the_rest() {
+        let history = history(&vec![(1, 0, 60); MAX_VOTES][..]);
+        let karma = karma(MAX_VOTES as u64 + 100, 0);
+        assert_eq!(round(decayed_karma(&karma, &history, HALF_LIFE, Local::now())), (MAX_VOTES + 100) as f64 / 4.0);
+    }
+}
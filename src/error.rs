@@ -1,11 +1,25 @@
+use parser::ParseError;
+
 #[derive(Debug)]
 pub struct KarmaError {
-    descr: String
+    descr: String,
+    parse_error: Option<ParseError>
 }
 
 impl KarmaError {
     pub fn new(descr: &str) -> KarmaError {
-        KarmaError { descr: descr.to_string() }
+        KarmaError { descr: descr.to_string(), parse_error: None }
+    }
+
+    /// Details about where and why parsing a message failed, if that's what this error is about.
+    pub fn parse_error(&self) -> Option<&ParseError> {
+        self.parse_error.as_ref()
+    }
+}
+
+impl From<ParseError> for KarmaError {
+    fn from(err: ParseError) -> KarmaError {
+        KarmaError { descr: err.to_string(), parse_error: Some(err) }
     }
 }
 
@@ -13,6 +27,10 @@ impl ::std::error::Error for KarmaError {
     fn description(&self) -> &str {
         &self.descr[..]
     }
+
+    fn cause(&self) -> Option<&::std::error::Error> {
+        self.parse_error.as_ref().map(|e| e as &::std::error::Error)
+    }
 }
 
 impl ::std::fmt::Display for KarmaError {
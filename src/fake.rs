@@ -0,0 +1,172 @@
+//! A DaZeus client that keeps everything in memory, so the handlers can be
+//! tested without a running DaZeus core.
+
+use rustc_serialize::json::{self, Json};
+use dazeus::{ConfigGroup, DaZeusClient, Event, EventType, Response, ResponseError, Scope};
+use std::sync::Mutex;
+use std::collections::HashMap;
+
+pub const NETWORK: &'static str = "example";
+pub const CHANNEL: &'static str = "#karma";
+
+type PropertyKey = (String, Option<String>, Option<String>, Option<String>);
+
+/// Something the plugin said in reply to an event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub network: String,
+    pub channel: String,
+    pub message: String,
+    pub highlight: bool
+}
+
+pub struct FakeDaZeus {
+    properties: Mutex<HashMap<PropertyKey, String>>,
+    replies: Mutex<Vec<Reply>>,
+    /// Senders that have every permission.
+    pub admins: Vec<String>,
+    /// Nicks that are identified with services, and the account they are identified to.
+    pub accounts: Vec<(String, String)>
+}
+
+impl FakeDaZeus {
+    pub fn new() -> FakeDaZeus {
+        FakeDaZeus {
+            properties: Mutex::new(HashMap::new()),
+            replies: Mutex::new(Vec::new()),
+            admins: Vec::new(),
+            accounts: Vec::new()
+        }
+    }
+
+    /// A message from `sender` in the test channel.
+    pub fn message(&self, sender: &str, message: &str) -> Event {
+        Event {
+            event: EventType::PrivMsg,
+            params: vec!(NETWORK.to_string(), sender.to_string(), CHANNEL.to_string(), message.to_string())
+        }
+    }
+
+    /// A command from `sender` in the test channel, `args` as typed after the command.
+    pub fn command(&self, sender: &str, command: &str, args: &str) -> Event {
+        let mut params = vec!(NETWORK.to_string(), sender.to_string(), CHANNEL.to_string(), command.to_string(), args.to_string());
+        params.extend(args.split_whitespace().map(|s| s.to_string()));
+        Event { event: EventType::Command(command.to_string()), params: params }
+    }
+
+    /// A nick change from `old` to `new`.
+    pub fn nick_change(&self, old: &str, new: &str) -> Event {
+        Event { event: EventType::Nick, params: vec!(NETWORK.to_string(), old.to_string(), new.to_string()) }
+    }
+
+    /// Everything replied so far, oldest first.
+    pub fn replies(&self) -> Vec<Reply> {
+        self.replies.lock().unwrap().clone()
+    }
+
+    /// The messages replied so far, clearing them.
+    pub fn take_replies(&self) -> Vec<String> {
+        self.replies.lock().unwrap().drain(..).map(|r| r.message).collect()
+    }
+
+    pub fn property(&self, name: &str, scope: Scope) -> Option<String> {
+        self.properties.lock().unwrap().get(&key(name, &scope)).cloned()
+    }
+}
+
+fn key(name: &str, scope: &Scope) -> PropertyKey {
+    (name.to_string(), scope.network.clone(), scope.target.clone(), scope.sender.clone())
+}
+
+fn value(value: Option<String>) -> Response {
+    let mut obj = json::Object::new();
+    obj.insert("success".to_string(), Json::Boolean(true));
+    if let Some(v) = value {
+        obj.insert("value".to_string(), Json::String(v));
+    }
+    Response::from_json(Json::Object(obj))
+}
+
+impl DaZeusClient for FakeDaZeus {
+    fn networks(&self) -> Vec<String> {
+        vec!(NETWORK.to_string())
+    }
+
+    fn channels(&self, _: &str) -> Vec<String> {
+        vec!(CHANNEL.to_string())
+    }
+
+    fn message(&self, network: &str, channel: &str, message: &str) -> Response {
+        self.replies.lock().unwrap().push(Reply {
+            network: network.to_string(),
+            channel: channel.to_string(),
+            message: message.to_string(),
+            highlight: false
+        });
+        value(None)
+    }
+
+    fn join(&self, _: &str, _: &str) -> Response {
+        value(None)
+    }
+
+    fn nick(&self, _: &str) -> Result<String, ResponseError> {
+        Ok("DaZeus".to_string())
+    }
+
+    fn whois(&self, network: &str, nick: &str) -> Event {
+        let mut params = vec!(network.to_string(), nick.to_string());
+        match self.accounts.iter().find(|&&(ref n, _)| n == nick) {
+            Some(&(_, ref account)) => params.extend(vec!("true".to_string(), account.clone())),
+            None => params.push("false".to_string()),
+        }
+        Event { event: EventType::PrivMsg, params: params }
+    }
+
+    fn get_config(&self, _: &str, _: ConfigGroup) -> Response {
+        value(None)
+    }
+
+    fn get_highlight_char(&self) -> Result<String, ResponseError> {
+        Ok("}".to_string())
+    }
+
+    fn get_property(&self, name: &str, scope: Scope) -> Response {
+        value(self.property(name, scope))
+    }
+
+    fn set_property(&self, name: &str, value: &str, scope: Scope) -> Response {
+        self.properties.lock().unwrap().insert(key(name, &scope), value.to_string());
+        self::value(None)
+    }
+
+    fn unset_property(&self, name: &str, scope: Scope) -> Response {
+        self.properties.lock().unwrap().remove(&key(name, &scope));
+        value(None)
+    }
+
+    fn get_property_keys(&self, prefix: &str, scope: Scope) -> Vec<String> {
+        let wanted = key("", &scope);
+        self.properties.lock().unwrap().keys()
+            .filter(|k| k.0.starts_with(prefix) && (&k.1, &k.2, &k.3) == (&wanted.1, &wanted.2, &wanted.3))
+            .map(|k| k.0.clone())
+            .collect()
+    }
+
+    fn has_permission(&self, _: &str, default: bool, scope: Scope) -> bool {
+        match scope.sender {
+            Some(ref sender) if self.admins.contains(sender) => true,
+            _ => default,
+        }
+    }
+
+    fn reply(&self, event: &Event, message: &str, highlight: bool) -> Response {
+        self.replies.lock().unwrap().push(Reply {
+            network: event[0].to_string(),
+            channel: event[2].to_string(),
+            message: message.to_string(),
+            highlight: highlight
+        });
+        value(None)
+    }
+}
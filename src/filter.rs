@@ -0,0 +1,159 @@
+use rustc_serialize::json;
+use rustc_serialize::json::ToJson;
+use dazeus::{DaZeusClient, Scope};
+use config::Config;
+use error::KarmaError;
+use store::{KarmaStore, Record};
+use term::normalize;
+use regex::Regex;
+use std::error::Error;
+
+/// Which of the two term lists a pattern is on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterList {
+    Deny,
+    Allow
+}
+
+impl FilterList {
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            FilterList::Deny => "deny",
+            FilterList::Allow => "allow",
+        }
+    }
+}
+
+/// A term on a filter list, either written out or as a `/regex/`. Regular
+/// expressions are matched against the normalized term.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Exact(String),
+    Regex(Regex)
+}
+
+impl Pattern {
+    pub fn from_str(s: &str) -> Result<Pattern, Box<Error>> {
+        let s = s.trim();
+        if s.len() > 2 && s.starts_with("/") && s.ends_with("/") {
+            Ok(Pattern::Regex(try!(Regex::new(&s[1..s.len() - 1]))))
+        } else if normalize(strip_modifier(s)).len() > 0 {
+            Ok(Pattern::Exact(normalize(strip_modifier(s))))
+        } else {
+            Err(From::from(KarmaError::new("An empty term can't be on a list")))
+        }
+    }
+
+    /// Check the pattern against a normalized term. Regular expressions may
+    /// match any part of the term, unless they are anchored with `^` and `$`.
+    pub fn matches(&self, term: &str) -> bool {
+        match *self {
+            Pattern::Exact(ref s) => s == term,
+            Pattern::Regex(ref re) => re.is_match(term),
+        }
+    }
+
+    pub fn to_string(&self) -> String {
+        match *self {
+            Pattern::Exact(ref s) => s.clone(),
+            Pattern::Regex(ref re) => format!("/{}/", re.as_str()),
+        }
+    }
+}
+
+/// Decides which terms karma may be given to. A term on the deny list never
+/// gets karma, and when the allow list isn't empty only terms on it do.
+pub struct TermFilter {
+    deny: Vec<Pattern>,
+    allow: Vec<Pattern>
+}
+
+impl TermFilter {
+    /// Combine the lists from the configuration with those managed at runtime.
+    pub fn load(store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope, config: &Config) -> TermFilter {
+        let parse = |list: FilterList, configured: &Vec<String>| -> Vec<Pattern> {
+            let stored = stored_patterns(store, dazeus, scope.clone(), list);
+            configured.iter().chain(stored.iter()).filter_map(|p| {
+                match Pattern::from_str(&p[..]) {
+                    Ok(pattern) => Some(pattern),
+                    Err(e) => {
+                        warn!("Ignoring invalid {} pattern '{}': {}", list.to_str(), p, e);
+                        None
+                    }
+                }
+            }).collect()
+        };
+
+        TermFilter {
+            deny: parse(FilterList::Deny, &config.deny),
+            allow: parse(FilterList::Allow, &config.allow)
+        }
+    }
+
+    pub fn is_allowed(&self, term: &str) -> bool {
+        let term = normalize(term);
+        if self.deny.iter().any(|p| p.matches(&term[..])) {
+            return false;
+        }
+        self.allow.len() == 0 || self.allow.iter().any(|p| p.matches(&term[..]))
+    }
+}
+
+/// Allow writing `c++` for the term `c`, as that is how it shows up in chat.
+fn strip_modifier(s: &str) -> &str {
+    if s.ends_with("++") || s.ends_with("--") { &s[..s.len() - 2] } else { s }
+}
+
+/// The patterns that were added to a list at runtime.
+pub fn stored_patterns(store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope, list: FilterList) -> Vec<String> {
+    let value = match store.get_record(dazeus, scope, Record::Filter, list.to_str()) {
+        Ok(Some(s)) => s,
+        Ok(None) => return Vec::new(),
+        Err(e) => {
+            warn!("Could not retrieve the stored {} list: {}", list.to_str(), e);
+            return Vec::new();
+        }
+    };
+
+    match json::Json::from_str(&value[..]) {
+        Ok(json::Json::Array(items)) => items.iter().filter_map(|i| i.as_string().map(|s| s.to_string())).collect(),
+        _ => {
+            warn!("Ignoring the stored {} list, it is not a list of patterns", list.to_str());
+            Vec::new()
+        }
+    }
+}
+
+/// Add a pattern to a list, returns the pattern as it was stored.
+pub fn add_pattern(store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope, list: FilterList, pattern: &str) -> Result<String, Box<Error>> {
+    let pattern = try!(Pattern::from_str(pattern)).to_string();
+    let mut patterns = stored_patterns(store, dazeus, scope.clone(), list);
+    if patterns.contains(&pattern) {
+        return Err(From::from(KarmaError::new(&format!("{} is already on the {} list", pattern, list.to_str())[..])));
+    }
+
+    patterns.push(pattern.clone());
+    try!(store.put_record(dazeus, scope, Record::Filter, list.to_str(), Some(&patterns.to_json().to_string()[..])));
+    Ok(pattern)
+}
+
+/// Remove a pattern from a list, returns whether it was on there.
+pub fn remove_pattern(store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope, list: FilterList, pattern: &str) -> Result<bool, Box<Error>> {
+    let pattern = match Pattern::from_str(pattern) {
+        Ok(p) => p.to_string(),
+        Err(_) => pattern.trim().to_string(),
+    };
+    let mut patterns = stored_patterns(store, dazeus, scope.clone(), list);
+    let before = patterns.len();
+    patterns.retain(|p| *p != pattern);
+    if patterns.len() == before {
+        return Ok(false);
+    }
+
+    if patterns.len() == 0 {
+        try!(store.put_record(dazeus, scope, Record::Filter, list.to_str(), None));
+    } else {
+        try!(store.put_record(dazeus, scope, Record::Filter, list.to_str(), Some(&patterns.to_json().to_string()[..])));
+    }
+    Ok(true)
+}
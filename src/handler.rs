@@ -1,31 +1,179 @@
 use dazeus::{DaZeusClient, Event, Scope};
-use super::grammar::line;
-use super::karma::{Karma, KarmaStyle, KarmaValue};
+use super::parser::line;
+use super::karma::{Karma, KarmaChange, KarmaStyle, KarmaValue};
+use super::config::{render, Config, SelfKarma};
+use super::ratelimit::{RateLimit, RateLimiter};
+use super::history::{Vote, VoterTotal};
+use super::trend::{bucket_totals, sparkline, Trend, TrendPeriod};
+use super::reason::{Reason, Reasons};
+use super::alias;
+use super::identity;
+use super::filter::TermFilter;
+use super::switch::{self, Feature};
+use super::store::KarmaStore;
+use super::term::normalize;
+use super::decay;
 use std::error::Error;
-use rustc_serialize::json::ToJson;
-use std::ascii::AsciiExt;
-
-pub fn handle_karma_events(evt: &Event, dazeus: &DaZeusClient) {
-    match line(&evt[3]) {
-        Ok(changes) => {
-            let totals = get_change_totals(changes);
-            for change in totals {
-                let value = store_karma_change(&change, Scope::network(&evt[0]), dazeus).unwrap();
-                if change.style == KarmaStyle::Notify {
-                    let updown = if change.change.total() < 0 { "decreased" } else { "increased" };
-                    dazeus.reply(&evt, &format!("{} {} the karma of {} to {}", &evt[1], updown, change.term, value.votes.to_string())[..], false);
+use std::cmp::{min, Ordering};
+use std::sync::atomic::{self, AtomicUsize, ATOMIC_USIZE_INIT};
+
+const DEFAULT_RANKING_SIZE: usize = 5;
+const MAX_RANKING_SIZE: usize = 20;
+/// Leaves room for the rest of the IRC line, which can't be longer than 512 bytes.
+const MAX_RANKING_LENGTH: usize = 400;
+const MAX_VOTERS_SHOWN: usize = 3;
+
+/// The number of messages with karma in them that we couldn't make sense of.
+static PARSE_ERRORS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+pub fn handle_karma_events(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore, limiter: &mut RateLimiter) {
+    if config.ignore_quotes && is_quoted(&evt[3]) {
+        return;
+    }
+
+    let parsed = line(&evt[3]);
+    if parsed.changes.len() == 0 && parsed.errors.len() == 0 {
+        return;
+    }
+
+    // looking up the switch takes a round trip to DaZeus, so not for every line
+    if !switch::is_enabled(dazeus, config, &evt[0], &evt[2], Feature::Votes) {
+        return;
+    }
+
+    for e in parsed.errors.iter() {
+        let count = PARSE_ERRORS.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+        match e.parse_error() {
+            Some(err) => warn!("Ignoring karma I don't understand in '{}/{}' from '{}' ({} so far): {}: {} <-- here --> {}",
+                               &evt[0], &evt[2], &evt[1], count, err, &evt[3][..err.offset], &evt[3][err.offset..]),
+            None => warn!("Ignoring karma I don't understand in '{}/{}' from '{}' ({} so far): {}", &evt[0], &evt[2], &evt[1], count, e),
+        }
+    }
+
+    if parsed.changes.len() == 0 {
+        return;
+    }
+
+    // before looking at the terms, so the voter's nick is known when they vote on a variant of it
+    let voter = identity::canonical_nick(dazeus, config, &evt[0], &evt[1]);
+    let changes = parsed.changes.into_iter()
+        .filter(|c| config.syntaxes.contains(&c.style) && config.operators.contains(&c.operator))
+        .map(|mut c| {
+            let identity = identity::canonical(dazeus, config, &evt[0], &c.term[..]);
+            if identity != normalize(&c.term[..]) {
+                c.term = identity;
+            }
+            c
+        })
+        .collect();
+    // capped per term rather than per change, or repeating a change would get around it
+    let mut totals: Vec<Karma> = get_change_totals(changes).into_iter()
+        .map(|mut c| {
+            c.change = KarmaChange::new(min(c.change.up, config.max_change), min(c.change.down, config.max_change));
+            c
+        })
+        .filter(|c| c.change.up != c.change.down)
+        .collect();
+    if totals.len() > 0 {
+        let filter = TermFilter::load(store, dazeus, read_scope(evt, config), config);
+        totals.retain(|c| {
+            let allowed = filter.is_allowed(&c.term[..]);
+            if !allowed {
+                debug!("Ignoring vote on filtered term '{}' in '{}/{}'", c.term, &evt[0], &evt[2]);
+            }
+            allowed
+        });
+    }
+    for mut change in totals {
+        if config.is_nick(&evt[1], &change.term[..]) || config.is_nick(&voter[..], &change.term[..]) {
+            // the message explains why the vote didn't count the way it was meant to
+            if config.self_karma != SelfKarma::Allow {
+                if let Some(ref msg) = config.self_karma_message {
+                    dazeus.reply(&evt, &render(msg, &[("nick", &evt[1]), ("term", &change.term[..])])[..], true);
+                }
+            }
+
+            match config.self_karma {
+                SelfKarma::Allow => (),
+                SelfKarma::Reject => continue,
+                SelfKarma::Invert => change.change = KarmaChange::new(change.change.down, change.change.up),
+            }
+        }
+
+        let votes = (change.change.up + change.change.down) as usize;
+        match limiter.check(&evt[0], &voter[..], &change.term[..], votes) {
+            RateLimit::Allowed(allowed) if allowed < votes => {
+                info!("Counted {} of {} votes on '{}' by '{}' in '{}/{}' because of rate limiting", allowed, votes, change.term, &evt[1], &evt[0], &evt[2]);
+                change.change = trim_change(change.change, allowed as u64);
+            },
+            RateLimit::Allowed(_) => (),
+            RateLimit::Limited { warn } => {
+                info!("Dropped vote on '{}' by '{}' in '{}/{}' because of rate limiting", change.term, &evt[1], &evt[0], &evt[2]);
+                if warn && config.rate_limit_warning {
+                    dazeus.reply(&evt, &render(&config.rate_limit_message[..], &[("nick", &evt[1])])[..], true);
                 }
+                continue;
             }
         }
-        Err(_) => warn!("Got a message I don't understand in '{}/{}' from '{}': {}", &evt[0], &evt[2], &evt[1], &evt[3])
+
+        let mut values = Vec::new();
+        for scope in config.scope.write_scopes(&evt[0], &evt[2]) {
+            let value = match store_karma_change(&change, evt, scope.clone(), store, dazeus) {
+                Ok(value) => value,
+                Err(e) => {
+                    warn!("Could not count the vote on '{}' by '{}' in '{}/{}': {}", change.term, &evt[1], &evt[0], &evt[2], e);
+                    continue;
+                }
+            };
+            if let Err(e) = record_trend(&change, &value.term[..], scope.clone(), store, dazeus) {
+                warn!("Could not update the trend of '{}': {}", change.term, e);
+            }
+            if let Err(e) = record_reason(&change, &value.term[..], evt, scope, store, dazeus) {
+                warn!("Could not remember why '{}' voted on '{}': {}", &evt[1], change.term, e);
+            }
+            values.push(value);
+        }
+
+        let value = match values.first() {
+            Some(value) => value,
+            None => continue,
+        };
+        if change.style == KarmaStyle::Notify && switch::is_enabled(dazeus, config, &evt[0], &evt[2], Feature::Notify) {
+            let updown = if change.change.total() < 0 { "decreased" } else { "increased" };
+            let msg = render(&config.notify_message[..], &[
+                ("voter", &evt[1]),
+                ("direction", updown),
+                ("term", &change.term[..]),
+                ("karma", &value.votes.to_string()[..])
+            ]);
+            dazeus.reply(&evt, &msg[..], false);
+        }
+    }
+}
+
+/// Drop votes from a change until no more than `votes` are left, those
+/// against the direction of the change first.
+fn trim_change(change: KarmaChange, votes: u64) -> KarmaChange {
+    if change.up >= change.down {
+        let up = min(change.up, votes);
+        KarmaChange::new(up, min(change.down, votes - up))
+    } else {
+        let down = min(change.down, votes);
+        KarmaChange::new(min(change.up, votes - down), down)
     }
 }
 
-pub fn reply_to_karma_command(evt: &Event, dazeus: &DaZeusClient) {
+/// Whether someone is quoting what was said earlier, rather than voting again.
+fn is_quoted(message: &str) -> bool {
+    message.trim_left().starts_with(">")
+}
+
+pub fn reply_to_karma_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
     let term = &evt[4].trim();
     if term != &"" {
-        let karma = match KarmaValue::from_dazeus(dazeus, Scope::network(&evt[0]), term) {
-            Ok(karma) => karma,
+        let identity = identity::canonical(dazeus, config, &evt[0], term);
+        let karma = match KarmaValue::load(store, dazeus, read_scope(evt, config), config, &identity[..]) {
+            Ok(Some(karma)) => karma,
             _ => KarmaValue::new(term),
         };
         dazeus.reply(&evt, &karma.to_string()[..], false);
@@ -34,9 +182,9 @@ pub fn reply_to_karma_command(evt: &Event, dazeus: &DaZeusClient) {
     }
 }
 
-pub fn reply_to_karmafight_command(evt: &Event, dazeus: &DaZeusClient) {
+pub fn reply_to_karmafight_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
     if evt.len() > 5 {
-        let karmas = retrieve_all_karmas(evt, dazeus);
+        let karmas = retrieve_all_karmas(evt, dazeus, config, store);
         if karmas.len() == 1 {
             dazeus.reply(&evt, "What kind of fight would this be?", true);
         } else {
@@ -44,10 +192,10 @@ pub fn reply_to_karmafight_command(evt: &Event, dazeus: &DaZeusClient) {
 
             if highest.len() == 1 {
                 let first = highest.first().unwrap();
-                dazeus.reply(&evt, &format!("{} wins with {}", first.original_term, first.votes.to_string())[..], false);
+                dazeus.reply(&evt, &format!("{} wins with {}", first.original_term, first.karma_to_string())[..], false);
             } else {
                 let terms = highest.iter().map(|e| e.original_term.clone() ).collect::<Vec<String>>().connect(", ");
-                let karma = highest.first().unwrap().votes.total();
+                let karma = highest.first().unwrap().score_to_string();
                 dazeus.reply(&evt, &format!("{} all have the same karma: {}", terms, karma)[..], false);
             }
         }
@@ -56,16 +204,194 @@ pub fn reply_to_karmafight_command(evt: &Event, dazeus: &DaZeusClient) {
     }
 }
 
+pub fn reply_to_karmawho_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
+    let term = &evt[4].trim();
+    if term == &"" {
+        dazeus.reply(&evt, "Whose voters do you want to know about?", true);
+        return;
+    }
+
+    let scope = read_scope(evt, config);
+    let identity = identity::canonical(dazeus, config, &evt[0], term);
+    let resolved = alias::resolve(store, dazeus, scope.clone(), &identity[..]);
+    let history = match store.history(dazeus, scope, &resolved[..]) {
+        Ok(history) => history,
+        Err(e) => {
+            warn!("Could not read vote history of '{}' in '{}': {}", term, &evt[0], e);
+            dazeus.reply(&evt, "I can't seem to remember who voted on that", true);
+            return;
+        }
+    };
+
+    if history.votes.len() == 0 {
+        dazeus.reply(&evt, &format!("Nobody has voted on {} yet", term)[..], false);
+        return;
+    }
+
+    let describe = |totals: Vec<VoterTotal>, up: bool| -> String {
+        if totals.len() == 0 {
+            "nobody".to_string()
+        } else {
+            totals.iter().take(MAX_VOTERS_SHOWN).map(|e| {
+                if up { format!("{} (+{})", e.voter, e.votes.up) } else { format!("{} (-{})", e.voter, e.votes.down) }
+            }).collect::<Vec<String>>().connect(", ")
+        }
+    };
+
+    let upvoters = describe(history.voter_totals(true), true);
+    let downvoters = describe(history.voter_totals(false), false);
+    dazeus.reply(&evt, &format!("{} was upvoted by {} and downvoted by {}", term, upvoters, downvoters)[..], false);
+}
+
+pub fn reply_to_karmatrend_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
+    if evt.len() < 6 || evt.len() > 7 {
+        dazeus.reply(&evt, "Use `karmatrend term [day|week|month|year]` to see how karma developed", true);
+        return;
+    }
+
+    let period = if evt.len() == 7 {
+        match TrendPeriod::from_str(&evt[6]) {
+            Some(p) => p,
+            None => {
+                dazeus.reply(&evt, "I only know about trends over a day, week, month or year", true);
+                return;
+            }
+        }
+    } else {
+        TrendPeriod::Week
+    };
+
+    let scope = read_scope(evt, config);
+    let identity = identity::canonical(dazeus, config, &evt[0], &evt[5]);
+    let term = alias::resolve(store, dazeus, scope.clone(), &identity[..]);
+    let trend = match Trend::load(store, dazeus, scope, &term[..]) {
+        Ok(trend) => trend,
+        Err(e) => {
+            warn!("Could not read the trend of '{}' in '{}': {}", term, &evt[0], e);
+            dazeus.reply(&evt, "I can't seem to remember how that went", true);
+            return;
+        }
+    };
+
+    let totals = trend.daily_totals(period.days());
+    let net = totals.iter().fold(0, |a, b| a + b);
+    let change = match net {
+        0 => format!("{} didn't change in karma over the last {}", &evt[5], period.to_str()),
+        n if n > 0 => format!("{} gained {} karma over the last {}", &evt[5], n, period.to_str()),
+        n => format!("{} lost {} karma over the last {}", &evt[5], -n, period.to_str()),
+    };
+
+    if totals.len() > 1 {
+        let points = bucket_totals(&totals[..], period.days_per_point());
+        dazeus.reply(&evt, &format!("{} {}", change, sparkline(&points[..]))[..], false);
+    } else {
+        dazeus.reply(&evt, &change[..], false);
+    }
+}
+
+pub fn reply_to_karmawhy_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
+    let term = &evt[4].trim();
+    if term == &"" {
+        dazeus.reply(&evt, "Why who got karma?", true);
+        return;
+    }
+
+    let scope = read_scope(evt, config);
+    let identity = identity::canonical(dazeus, config, &evt[0], term);
+    let resolved = alias::resolve(store, dazeus, scope.clone(), &identity[..]);
+    let reasons = match Reasons::load(store, dazeus, scope, &resolved[..]) {
+        Ok(reasons) => reasons,
+        Err(e) => {
+            warn!("Could not read the reasons for '{}' in '{}': {}", term, &evt[0], e);
+            dazeus.reply(&evt, "I can't seem to remember why", true);
+            return;
+        }
+    };
+
+    let describe = |reason: Option<&Reason>, up: bool| -> Option<String> {
+        reason.map(|r| format!("{}{}: {} ({})", term, if up { "++" } else { "--" }, r.reason, r.voter))
+    };
+
+    let explained = vec!(describe(reasons.latest(true), true), describe(reasons.latest(false), false))
+        .into_iter()
+        .filter_map(|r| r)
+        .collect::<Vec<String>>();
+    if explained.len() == 0 {
+        dazeus.reply(&evt, &format!("Nobody has said why they voted on {}", term)[..], false);
+    } else {
+        dazeus.reply(&evt, &explained.connect("; ")[..], false);
+    }
+}
+
+pub fn reply_to_karmatop_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
+    reply_with_ranking(evt, dazeus, config, store, true);
+}
+
+pub fn reply_to_karmabottom_command(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) {
+    reply_with_ranking(evt, dazeus, config, store, false);
+}
+
+fn reply_with_ranking(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore, highest: bool) {
+    let count = if evt.len() > 5 {
+        match evt[5].parse::<usize>() {
+            Ok(n) if n > 0 && n <= MAX_RANKING_SIZE => n,
+            _ => {
+                dazeus.reply(&evt, &format!("Please give me a number between 1 and {}", MAX_RANKING_SIZE)[..], true);
+                return;
+            }
+        }
+    } else {
+        DEFAULT_RANKING_SIZE
+    };
+
+    let karmas = retrieve_stored_karmas(evt, dazeus, config, store);
+    if karmas.len() == 0 {
+        dazeus.reply(&evt, "Nobody has given out any karma yet", true);
+        return;
+    }
+
+    let (ranked, mut tied) = rank_karmas(karmas, count, highest);
+    let title = if highest { "Highest karma" } else { "Lowest karma" };
+    let mut list = Vec::new();
+    let mut length = title.len();
+    for karma in ranked.iter() {
+        let entry = format!("{} ({})", karma.original_term, karma.score_to_string());
+        length += entry.len() + 2;
+        if list.len() > 0 && length > MAX_RANKING_LENGTH {
+            break;
+        }
+        list.push(entry);
+    }
+    let cut = ranked.len() - list.len();
+
+    let mut msg = format!("{}: {}", title, list.connect(", "));
+    if cut > 0 {
+        msg.push_str(&format!(" and {} more", cut + tied)[..]);
+        tied = 0;
+    }
+    if tied > 0 {
+        msg.push_str(&format!(" and {} more tied", tied)[..]);
+    }
+    dazeus.reply(&evt, &msg[..], false);
+}
+
+pub fn read_scope(evt: &Event, config: &Config) -> Scope {
+    config.scope.read_scope(&evt[0], &evt[2])
+}
+
 fn get_change_totals(changes: Vec<Karma>) -> Vec<Karma> {
     // collect changes for every term in a single struct
     let mut totals: Vec<Karma> = Vec::new();
     for current in changes {
         let updated = {
-            match totals.iter_mut().find(|elem| elem.term == current.term) {
+            match totals.iter_mut().find(|elem| normalize(&elem.term[..]) == normalize(&current.term[..])) {
                 Some(elem) => {
                     elem.change.up += current.change.up;
                     elem.change.down += current.change.down;
                     elem.style = KarmaStyle::most_explicit(elem.style, current.style);
+                    if current.reason.is_some() {
+                        elem.reason = current.reason.clone();
+                    }
                     true
                 },
                 None => false,
@@ -83,24 +409,35 @@ fn get_change_totals(changes: Vec<Karma>) -> Vec<Karma> {
         .collect()
 }
 
-fn store_karma_change(change: &Karma, scope: Scope, dazeus: &DaZeusClient) -> Result<KarmaValue, Box<Error>> {
-    let property = format!("{}{}", ::karma::STORE_PREFIX, &change.term[..]);
-    let current = dazeus.get_property(&property[..].to_ascii_lowercase(), scope.clone());
-    let mut karma = match current.get_str("value") {
-        Some(s) => try!(KarmaValue::from_str(s)),
-        None => KarmaValue::new(&change.term[..]),
+fn store_karma_change(change: &Karma, evt: &Event, scope: Scope, store: &KarmaStore, dazeus: &DaZeusClient) -> Result<KarmaValue, Box<Error>> {
+    let mut vote = Vote::new(&evt[1], &evt[2], change);
+    vote.term = alias::resolve(store, dazeus, scope.clone(), &change.term[..]);
+    store.apply_vote(dazeus, scope, &vote)
+}
+
+fn record_trend(change: &Karma, term: &str, scope: Scope, store: &KarmaStore, dazeus: &DaZeusClient) -> Result<(), Box<Error>> {
+    let mut trend = try!(Trend::load(store, dazeus, scope.clone(), term));
+    trend.vote(change);
+    trend.save(store, dazeus, scope)
+}
+
+fn record_reason(change: &Karma, term: &str, evt: &Event, scope: Scope, store: &KarmaStore, dazeus: &DaZeusClient) -> Result<(), Box<Error>> {
+    let text = match change.reason {
+        Some(ref text) => text,
+        None => return Ok(()),
     };
-    karma.vote(change);
-    dazeus.set_property(&property[..].to_ascii_lowercase(), &karma.to_json().to_string()[..], scope.clone());
-    Ok(karma)
+
+    let mut reasons = try!(Reasons::load(store, dazeus, scope.clone(), term));
+    reasons.add(Reason::new(&evt[1], &text[..], change.change.total() > 0));
+    reasons.save(store, dazeus, scope)
 }
 
 fn find_highest_karma(karmas: Vec<KarmaValue>) -> Vec<KarmaValue> {
     let mut highest: Vec<KarmaValue> = Vec::new();
     for item in karmas {
-        if highest.len() == 0 || highest.first().unwrap().votes.total() == item.votes.total() {
+        if highest.len() == 0 || highest.first().unwrap().score() == item.score() {
             highest.push(item);
-        } else if item.votes.total() > highest.first().unwrap().votes.total() {
+        } else if item.score() > highest.first().unwrap().score() {
             highest.clear();
             highest.push(item);
         }
@@ -110,15 +447,323 @@ fn find_highest_karma(karmas: Vec<KarmaValue>) -> Vec<KarmaValue> {
     highest
 }
 
-fn retrieve_all_karmas(evt: &Event, dazeus: &DaZeusClient) -> Vec<KarmaValue> {
+/// The `count` highest or lowest karmas, along with the terms tied with the last
+/// of them, up to `MAX_RANKING_SIZE` terms. Also returns the number of tied
+/// terms that didn't fit.
+fn rank_karmas(mut karmas: Vec<KarmaValue>, count: usize, highest: bool) -> (Vec<KarmaValue>, usize) {
+    // terms with equal karma are ordered the same way find_highest_karma does
+    karmas.sort_by(|a, b| {
+        let order = if highest {
+            b.score().partial_cmp(&a.score())
+        } else {
+            a.score().partial_cmp(&b.score())
+        }.unwrap_or(Ordering::Equal);
+        match order {
+            Ordering::Equal => a.votes.up.cmp(&b.votes.up),
+            o => o,
+        }
+    });
+
+    // don't cut off terms that are tied with the last one in the ranking
+    let mut ranked: Vec<KarmaValue> = Vec::new();
+    let mut tied = 0;
+    for item in karmas {
+        if ranked.len() >= count && ranked.last().unwrap().score() != item.score() {
+            break;
+        }
+        if ranked.len() >= MAX_RANKING_SIZE {
+            tied += 1;
+        } else {
+            ranked.push(item);
+        }
+    }
+    (ranked, tied)
+}
+
+fn retrieve_stored_karmas(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) -> Vec<KarmaValue> {
+    let scope = read_scope(evt, config);
+    let mut karmas = match store.list(dazeus, scope.clone()) {
+        Ok(karmas) => karmas,
+        Err(e) => {
+            warn!("Could not read the stored karma in '{}': {}", &evt[0], e);
+            return Vec::new();
+        }
+    };
+    for karma in karmas.iter_mut() {
+        decay::apply_decay(karma, store, dazeus, scope.clone(), config);
+    }
+    karmas
+}
+
+fn retrieve_all_karmas(evt: &Event, dazeus: &DaZeusClient, config: &Config, store: &KarmaStore) -> Vec<KarmaValue> {
     let mut karmas = Vec::new();
     for key in 5..evt.len() {
-        if !karmas.iter().any(|e: &KarmaValue| e.term == &evt[key]) {
-            karmas.push(match KarmaValue::from_dazeus(dazeus, Scope::network(&evt[0]), &evt[key]) {
-                Ok(karma) => karma,
+        let term = identity::canonical(dazeus, config, &evt[0], &evt[key]);
+        if !karmas.iter().any(|e: &KarmaValue| e.term == term) {
+            karmas.push(match KarmaValue::load(store, dazeus, read_scope(evt, config), config, &term[..]) {
+                Ok(Some(karma)) => karma,
                 _ => KarmaValue::new(&evt[key])
             });
         }
     }
     karmas
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+    use fake::{FakeDaZeus, CHANNEL, NETWORK};
+    use ratelimit::RateLimiter;
+    use store::{KarmaStore, MemoryStore};
+    use dazeus::Scope;
+
+    struct Plugin {
+        dazeus: FakeDaZeus,
+        config: Config,
+        store: MemoryStore,
+        limiter: RateLimiter
+    }
+
+    impl Plugin {
+        fn new() -> Plugin {
+            let config = Config::new();
+            Plugin {
+                dazeus: FakeDaZeus::new(),
+                limiter: RateLimiter::new(&config),
+                config: config,
+                store: MemoryStore::new()
+            }
+        }
+
+        fn say(&mut self, sender: &str, message: &str) {
+            let evt = self.dazeus.message(sender, message);
+            handle_karma_events(&evt, &self.dazeus, &self.config, &self.store, &mut self.limiter);
+        }
+
+        fn votes(&self, term: &str) -> Option<(u64, u64)> {
+            self.store.get(&self.dazeus, Scope::network(NETWORK), term).unwrap().map(|k| (k.votes.up, k.votes.down))
+        }
+    }
+
+    #[test]
+    fn votes_are_counted() {
+        let mut plugin = Plugin::new();
+        plugin.say("alice", "foo++ bar-- (baz quux) +1");
+        plugin.say("bob", "Foo++");
+        assert_eq!(plugin.votes("foo"), Some((2, 0)));
+        assert_eq!(plugin.votes("bar"), Some((0, 1)));
+        assert_eq!(plugin.votes("baz quux"), Some((1, 0)));
+        assert_eq!(plugin.store.history(&plugin.dazeus, Scope::network(NETWORK), "foo").unwrap().votes.len(), 2);
+    }
+
+    #[test]
+    fn votes_next_to_karma_we_dont_understand_are_counted() {
+        let mut plugin = Plugin::new();
+        plugin.say("alice", "[]++ foo++");
+        plugin.say("bob", "x += 99999999999999999999 foo++");
+        assert_eq!(plugin.votes("foo"), Some((2, 0)));
+    }
+
+    #[test]
+    fn repeated_changes_are_capped_together() {
+        let mut plugin = Plugin::new();
+        plugin.config.term_burst = 0;
+        plugin.limiter = RateLimiter::new(&plugin.config);
+        plugin.say("alice", "foo += 5 foo += 5 foo += 5 foo += 5");
+        plugin.say("bob", "bar++ bar++ bar++ bar++ bar++ bar++ bar++");
+        plugin.say("carol", "baz += 3 baz += 3 baz -= 1");
+        assert_eq!(plugin.votes("foo"), Some((5, 0)));
+        assert_eq!(plugin.votes("bar"), Some((5, 0)));
+        assert_eq!(plugin.votes("baz"), Some((5, 1)));
+    }
+
+    #[test]
+    fn every_vote_in_a_message_counts_towards_the_rate_limit() {
+        let mut plugin = Plugin::new();
+        plugin.say("alice", "foo += 5");
+        assert_eq!(plugin.votes("foo"), Some((2, 0)));
+
+        plugin.config.term_burst = 0;
+        plugin.config.max_change = 100;
+        plugin.limiter = RateLimiter::new(&plugin.config);
+        plugin.say("alice", &"bar++ ".repeat(100)[..]);
+        plugin.say("alice", "baz++");
+        assert_eq!(plugin.votes("bar"), Some((10, 0)));
+        assert_eq!(plugin.votes("baz"), None);
+    }
+
+    #[test]
+    fn changing_nick_does_not_reset_the_rate_limit() {
+        let mut plugin = Plugin::new();
+        plugin.config.identities = true;
+        plugin.say("alice", "foo += 2");
+        plugin.say("alice_", "foo++");
+        assert_eq!(plugin.votes("foo"), Some((2, 0)));
+    }
+
+    #[test]
+    fn changes_are_trimmed_against_their_direction_first() {
+        assert_eq!(trim_change(KarmaChange::new(3, 1), 2), KarmaChange::new(2, 0));
+        assert_eq!(trim_change(KarmaChange::new(3, 1), 3), KarmaChange::new(3, 0));
+        assert_eq!(trim_change(KarmaChange::new(1, 4), 3), KarmaChange::new(0, 3));
+        assert_eq!(trim_change(KarmaChange::new(2, 1), 5), KarmaChange::new(2, 1));
+    }
+
+    #[test]
+    fn votes_on_nick_variants_count_towards_the_nick() {
+        let mut plugin = Plugin::new();
+        plugin.config.identities = true;
+        plugin.dazeus.accounts.push(("alice".to_string(), "alice".to_string()));
+        plugin.say("alice", "alice_++ my_var_++");
+        assert_eq!(plugin.votes("alice"), None);
+        plugin.say("bob", "alice__++ (alice|away)++");
+        assert_eq!(plugin.votes("alice"), Some((2, 0)));
+        assert_eq!(plugin.votes("my_var_"), Some((1, 0)));
+    }
+
+    #[test]
+    fn votes_can_be_switched_off() {
+        let mut plugin = Plugin::new();
+        plugin.dazeus.admins.push("alice".to_string());
+        let evt = plugin.dazeus.command("alice", "karma", "off votes");
+        switch::reply_to_karma_switch_command(&evt, &plugin.dazeus, &plugin.config);
+        plugin.say("bob", "foo++");
+        assert_eq!(plugin.votes("foo"), None);
+
+        plugin.config.disabled_votes = vec!(NETWORK.to_string());
+        let evt = plugin.dazeus.command("alice", "karma", "on votes");
+        switch::reply_to_karma_switch_command(&evt, &plugin.dazeus, &plugin.config);
+        plugin.say("bob", "foo++");
+        assert_eq!(plugin.votes("foo"), Some((1, 0)));
+    }
+
+    #[test]
+    fn self_karma_is_rejected() {
+        let mut plugin = Plugin::new();
+        plugin.say("alice", "alice++");
+        assert_eq!(plugin.votes("alice"), None);
+    }
+
+    #[test]
+    fn self_karma_message_is_only_sent_when_the_vote_is_changed() {
+        let mut plugin = Plugin::new();
+        plugin.config.self_karma_message = Some("Nice try, {nick}".to_string());
+        plugin.say("alice", "alice++");
+        assert_eq!(plugin.dazeus.take_replies(), vec!("Nice try, alice"));
+
+        plugin.config.self_karma = SelfKarma::Allow;
+        plugin.say("bob", "bob++");
+        assert!(plugin.dazeus.take_replies().is_empty());
+        assert_eq!(plugin.votes("bob"), Some((1, 0)));
+    }
+
+    #[test]
+    fn only_notify_votes_get_a_reply() {
+        let mut plugin = Plugin::new();
+        plugin.say("alice", "foo++");
+        assert!(plugin.dazeus.replies().is_empty());
+
+        plugin.say("bob", "[foo]++");
+        let replies = plugin.dazeus.replies();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].channel, CHANNEL);
+        assert_eq!(replies[0].message, "bob increased the karma of foo to 2 (+2, -0)");
+        assert!(!replies[0].highlight);
+    }
+
+    #[test]
+    fn karma_command_replies_with_karma() {
+        let mut plugin = Plugin::new();
+        plugin.say("alice", "foo++");
+        plugin.say("bob", "foo-- foo--");
+
+        let evt = plugin.dazeus.command("carol", "karma", "foo");
+        reply_to_karma_command(&evt, &plugin.dazeus, &plugin.config, &plugin.store);
+        let evt = plugin.dazeus.command("carol", "karma", "unknown");
+        reply_to_karma_command(&evt, &plugin.dazeus, &plugin.config, &plugin.store);
+        assert_eq!(plugin.dazeus.take_replies(), vec!("foo has a karma of -1 (+1, -2)", "unknown has neutral karma (+0, -0)"));
+    }
+
+    #[test]
+    fn karmafight_picks_the_winner() {
+        let mut plugin = Plugin::new();
+        plugin.say("alice", "vim++ emacs++");
+        plugin.say("bob", "vim++");
+
+        let evt = plugin.dazeus.command("carol", "karmafight", "emacs vim nano");
+        reply_to_karmafight_command(&evt, &plugin.dazeus, &plugin.config, &plugin.store);
+        let evt = plugin.dazeus.command("carol", "karmafight", "emacs nano");
+        reply_to_karmafight_command(&evt, &plugin.dazeus, &plugin.config, &plugin.store);
+        plugin.say("carol", "nano++");
+        let evt = plugin.dazeus.command("dave", "karmafight", "emacs nano");
+        reply_to_karmafight_command(&evt, &plugin.dazeus, &plugin.config, &plugin.store);
+        assert_eq!(plugin.dazeus.take_replies(), vec!(
+            "vim wins with 2 (+2, -0)",
+            "emacs wins with 1 (+1, -0)",
+            "emacs, nano all have the same karma: 1"
+        ));
+    }
+
+    #[test]
+    fn quoted_lines_are_recognised() {
+        assert!(is_quoted("> foo++"));
+        assert!(is_quoted("  >bar--"));
+        assert!(!is_quoted("foo++ > bar++"));
+        assert!(!is_quoted("foo++"));
+    }
+
+    #[test]
+    fn quotes_are_ignored() {
+        let mut plugin = Plugin::new();
+        plugin.config.ignore_quotes = true;
+        plugin.say("alice", "> bob: foo++");
+        plugin.say("alice", "  >foo++");
+        assert_eq!(plugin.votes("foo"), None);
+        plugin.say("alice", "foo++ > bar");
+        assert_eq!(plugin.votes("foo"), Some((1, 0)));
+
+        plugin.config.ignore_quotes = false;
+        plugin.say("bob", "> foo++");
+        assert_eq!(plugin.votes("foo"), Some((2, 0)));
+    }
+
+    #[test]
+    fn karmatop_ranks_the_highest_karma() {
+        let mut plugin = Plugin::new();
+        plugin.say("alice", "foo++ bar++ baz--");
+        plugin.say("bob", "foo++");
+        let evt = plugin.dazeus.command("carol", "karmatop", "2");
+        reply_to_karmatop_command(&evt, &plugin.dazeus, &plugin.config, &plugin.store);
+        assert_eq!(plugin.dazeus.take_replies(), vec!("Highest karma: foo (2), bar (1)"));
+    }
+
+    #[test]
+    fn karmatop_caps_ties() {
+        let mut plugin = Plugin::new();
+        for i in 0..30 {
+            plugin.say(&format!("voter{}", i)[..], &format!("t{}++", i)[..]);
+        }
+
+        let evt = plugin.dazeus.command("bob", "karmatop", "");
+        reply_to_karmatop_command(&evt, &plugin.dazeus, &plugin.config, &plugin.store);
+        let reply = plugin.dazeus.take_replies().remove(0);
+        assert_eq!(reply.matches(" (1)").count(), MAX_RANKING_SIZE);
+        assert!(reply.ends_with(" and 10 more tied"));
+    }
+
+    #[test]
+    fn karmatop_fits_on_a_line() {
+        let mut plugin = Plugin::new();
+        for i in 0..10 {
+            plugin.say(&format!("voter{}", i)[..], &format!("[{}{}]++", i, "x".repeat(60))[..]);
+        }
+        plugin.dazeus.take_replies();
+
+        let evt = plugin.dazeus.command("bob", "karmatop", "10");
+        reply_to_karmatop_command(&evt, &plugin.dazeus, &plugin.config, &plugin.store);
+        let reply = plugin.dazeus.take_replies().remove(0);
+        assert!(reply.len() <= MAX_RANKING_LENGTH + 20, "{} bytes", reply.len());
+        assert!(reply.ends_with(" more"));
+    }
+}
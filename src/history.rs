@@ -0,0 +1,175 @@
+use rustc_serialize::json;
+use error::KarmaError;
+use chrono::{DateTime, Local};
+use karma::{format_time, Karma, KarmaChange, KarmaStyle};
+use term::normalize;
+use std::ascii::AsciiExt;
+
+/// How many votes are kept in the history of a single term.
+pub const MAX_VOTES: usize = 500;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vote {
+    pub voter: String,
+    pub channel: String,
+    pub term: String,
+    pub change: KarmaChange,
+    pub style: KarmaStyle,
+    pub time: DateTime<Local>,
+    /// Set by stores that need to find a vote back after writing it.
+    pub id: Option<String>
+}
+
+impl Vote {
+    pub fn new(voter: &str, channel: &str, karma: &Karma) -> Vote {
+        Vote {
+            voter: voter.to_string(),
+            channel: channel.to_string(),
+            term: normalize(&karma.term[..]),
+            change: karma.change,
+            style: karma.style,
+            time: Local::now(),
+            id: None
+        }
+    }
+
+    pub fn from_json(data: &json::Json) -> Result<Vote, Box<::std::error::Error>> {
+        macro_rules! get_key {
+            ($o:expr, $s:expr, $i_is:ident, $i_as:ident) => (match $o.get($s) {
+                Some(m) if m.$i_is() => m.$i_as().unwrap(),
+                _ => return Err(From::from(KarmaError::new(&format!("No value or invalid value for key '{}'", $s)[..])))
+            });
+        }
+
+        match data.as_object() {
+            Some(obj) => {
+                let style = get_key!(obj, "style", is_string, as_string);
+                let time = get_key!(obj, "time", is_string, as_string);
+
+                Ok(Vote {
+                    voter: get_key!(obj, "voter", is_string, as_string).to_string(),
+                    channel: get_key!(obj, "channel", is_string, as_string).to_string(),
+                    term: normalize(get_key!(obj, "term", is_string, as_string)),
+                    change: KarmaChange::new(get_key!(obj, "up", is_u64, as_u64), get_key!(obj, "down", is_u64, as_u64)),
+                    style: match KarmaStyle::from_str(style) {
+                        Some(s) => s,
+                        None => return Err(From::from(KarmaError::new(&format!("Unknown karma style '{}'", style)[..])))
+                    },
+                    time: try!(time.parse::<DateTime<Local>>()),
+                    id: obj.get("id").and_then(|id| id.as_string()).map(|id| id.to_string())
+                })
+            },
+            None => Err(From::from(KarmaError::new("Invalid json: vote is not an object")))
+        }
+    }
+}
+
+impl json::ToJson for Vote {
+    fn to_json(&self) -> json::Json {
+        let mut obj = json::Object::new();
+        obj.insert("voter".to_string(), self.voter.to_json());
+        obj.insert("channel".to_string(), self.channel.to_json());
+        obj.insert("term".to_string(), self.term.to_json());
+        obj.insert("up".to_string(), self.change.up.to_json());
+        obj.insert("down".to_string(), self.change.down.to_json());
+        obj.insert("style".to_string(), self.style.to_str().to_json());
+        obj.insert("time".to_string(), format_time(&self.time).to_json());
+        if let Some(ref id) = self.id {
+            obj.insert("id".to_string(), id.to_json());
+        }
+        obj.to_json()
+    }
+}
+
+/// Summarised votes of a single voter for some term.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoterTotal {
+    pub voter: String,
+    pub votes: KarmaChange
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoteHistory {
+    pub term: String,
+    pub votes: Vec<Vote>
+}
+
+impl VoteHistory {
+    pub fn new(term: &str) -> VoteHistory {
+        VoteHistory { term: normalize(term), votes: Vec::new() }
+    }
+
+    pub fn from_str(term: &str, s: &str) -> Result<VoteHistory, Box<::std::error::Error>> {
+        let data = try!(json::Json::from_str(s));
+        let list = match data.as_array() {
+            Some(list) => list,
+            None => return Err(From::from(KarmaError::new("Invalid json: vote history is not an array")))
+        };
+
+        let mut history = VoteHistory::new(term);
+        for item in list {
+            history.votes.push(try!(Vote::from_json(item)));
+        }
+        Ok(history)
+    }
+
+    /// Keep the votes of a stored history that can still be read.
+    pub fn salvage(term: &str, s: &str) -> VoteHistory {
+        let mut history = VoteHistory::new(term);
+        if let Ok(json::Json::Array(list)) = json::Json::from_str(s) {
+            history.votes = list.iter().filter_map(|item| Vote::from_json(item).ok()).collect();
+        }
+        history
+    }
+
+    /// Add the votes of another term to this history, keeping it ordered by time.
+    pub fn merge(&mut self, other: &VoteHistory) {
+        for vote in other.votes.iter() {
+            let mut vote = vote.clone();
+            vote.term = self.term.clone();
+            self.votes.push(vote);
+        }
+        self.votes.sort_by(|a, b| a.time.cmp(&b.time));
+        self.truncate();
+    }
+
+    /// Add a vote, forgetting about the oldest one if there are too many.
+    pub fn add(&mut self, vote: Vote) {
+        self.votes.push(vote);
+        self.truncate();
+    }
+
+    fn truncate(&mut self) {
+        if self.votes.len() > MAX_VOTES {
+            let excess = self.votes.len() - MAX_VOTES;
+            self.votes.drain(..excess);
+        }
+    }
+
+    /// Totals per voter, ordered by the number of upvotes (or downvotes) they gave.
+    pub fn voter_totals(&self, upvoters: bool) -> Vec<VoterTotal> {
+        let mut totals: Vec<VoterTotal> = Vec::new();
+        for vote in self.votes.iter() {
+            let updated = match totals.iter_mut().find(|e| e.voter.to_ascii_lowercase() == vote.voter.to_ascii_lowercase()) {
+                Some(elem) => {
+                    elem.votes.up += vote.change.up;
+                    elem.votes.down += vote.change.down;
+                    true
+                },
+                None => false,
+            };
+            if !updated {
+                totals.push(VoterTotal { voter: vote.voter.clone(), votes: vote.change });
+            }
+        }
+
+        if upvoters {
+            totals.retain(|e| e.votes.up > 0);
+            totals.sort_by(|a, b| b.votes.up.cmp(&a.votes.up));
+        } else {
+            totals.retain(|e| e.votes.down > 0);
+            totals.sort_by(|a, b| b.votes.down.cmp(&a.votes.down));
+        }
+        totals
+    }
+}
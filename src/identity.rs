@@ -0,0 +1,173 @@
+use dazeus::{DaZeusClient, Event, Scope};
+use config::Config;
+use term::normalize;
+
+/// Remove the decorations people add to their nick when they're away or
+/// reconnecting (`nick_`, `nick|away`, `nick^`) and the `:` or `,` used to
+/// address someone.
+pub fn strip_decorations(nick: &str) -> &str {
+    let stripped = nick.trim_right_matches(|c| c == ':' || c == ',');
+    let stripped = stripped.split('|').next().unwrap_or("");
+    let stripped = stripped.trim_right_matches(|c| c == '_' || c == '^' || c == '`');
+    if stripped.len() > 0 { stripped } else { nick }
+}
+
+/// The identity votes on `term` should count towards. Without identities
+/// enabled this is just the normalized term. Decorations are only removed from
+/// nicks we know, so `my_var_` stays what it is unless someone goes by `my_var`.
+pub fn canonical(dazeus: &DaZeusClient, config: &Config, network: &str, term: &str) -> String {
+    let normalized = normalize(term);
+    if !config.identities || term.trim().contains(char::is_whitespace) {
+        return normalized;
+    }
+
+    let nick = normalize(strip_decorations(term.trim()));
+    get_identity(dazeus, config, network, &normalized[..])
+        .or_else(|| get_identity(dazeus, config, network, &nick[..]))
+        .unwrap_or(normalized)
+}
+
+/// The identity of whoever is using `nick` right now, like the sender of a
+/// message. When they are identified with services to the account of the same
+/// name their nick is remembered, so votes on its variants are counted towards
+/// them from now on. Anyone else could be using a nick that isn't theirs.
+pub fn canonical_nick(dazeus: &DaZeusClient, config: &Config, network: &str, nick: &str) -> String {
+    if !config.identities {
+        return normalize(nick);
+    }
+
+    let stripped = normalize(strip_decorations(nick));
+    if let Some(identity) = get_identity(dazeus, config, network, &stripped[..]) {
+        return identity;
+    }
+
+    if identified_account(dazeus, network, nick).as_ref() == Some(&stripped) {
+        info!("Counting karma for variants of '{}' towards them in '{}'", stripped, network);
+        set_identity(dazeus, config, network, &stripped[..], &stripped[..]);
+    }
+    stripped
+}
+
+fn get_identity(dazeus: &DaZeusClient, config: &Config, network: &str, nick: &str) -> Option<String> {
+    let property = format!("{}{}", config.identity_prefix(), nick);
+    match dazeus.get_property(&property[..], Scope::network(network)).get_str("value") {
+        Some(s) if s.trim() != "" => Some(normalize(s)),
+        _ => None,
+    }
+}
+
+fn set_identity(dazeus: &DaZeusClient, config: &Config, network: &str, nick: &str, identity: &str) {
+    let property = format!("{}{}", config.identity_prefix(), nick);
+    dazeus.set_property(&property[..], identity, Scope::network(network));
+}
+
+/// Follow someone changing their nick, so karma for the new nick is counted
+/// towards whoever they were before. Only nicks that are identified with
+/// services to the account of that same person are followed, and a nick that
+/// already belongs to someone is never taken over.
+pub fn track_nick_change(evt: &Event, dazeus: &DaZeusClient, config: &Config) {
+    let network = &evt[0];
+    let old = canonical_nick(dazeus, config, network, &evt[1]);
+    let new = normalize(strip_decorations(&evt[2]));
+    if old == new || get_identity(dazeus, config, network, &new[..]).is_some() {
+        return;
+    }
+
+    match identified_account(dazeus, network, &evt[2]) {
+        Some(ref account) if *account == old => (),
+        Some(account) => {
+            info!("Not following nick change of '{}' to '{}' in '{}', the nick is identified as '{}'", &evt[1], &evt[2], network, account);
+            return;
+        },
+        None => {
+            debug!("Not following nick change of '{}' to '{}' in '{}', the nick isn't identified", &evt[1], &evt[2], network);
+            return;
+        }
+    }
+
+    info!("Counting karma for '{}' towards '{}' in '{}'", new, old, network);
+    if get_identity(dazeus, config, network, &old[..]).is_none() {
+        set_identity(dazeus, config, network, &old[..], &old[..]);
+    }
+    set_identity(dazeus, config, network, &new[..], &old[..]);
+}
+
+/// The services account a nick is identified to. The parameters of a WHOIS
+/// event are the network, the nick, whether it is identified and the account
+/// it is identified to.
+pub fn identified_account(dazeus: &DaZeusClient, network: &str, nick: &str) -> Option<String> {
+    let whois = dazeus.whois(network, nick);
+    let identified = whois.params.get(2).map_or(false, |i| i == "true" || i == "1");
+    match whois.params.get(3) {
+        Some(account) if identified && account.trim() != "" => Some(normalize(account)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+    use fake::{FakeDaZeus, NETWORK};
+
+    fn config() -> Config {
+        let mut config = Config::new();
+        config.identities = true;
+        config
+    }
+
+    #[test]
+    fn only_known_nicks_lose_their_decorations() {
+        let mut dazeus = FakeDaZeus::new();
+        dazeus.accounts.push(("Ruben".to_string(), "ruben".to_string()));
+        let config = config();
+        assert_eq!(canonical(&dazeus, &config, NETWORK, "my_var_"), "my_var_");
+        assert_eq!(canonical(&dazeus, &config, NETWORK, "ruben|away"), "ruben|away");
+
+        assert_eq!(canonical_nick(&dazeus, &config, NETWORK, "Ruben"), "ruben");
+        assert_eq!(canonical(&dazeus, &config, NETWORK, "ruben_"), "ruben");
+        assert_eq!(canonical(&dazeus, &config, NETWORK, "Ruben|away"), "ruben");
+        assert_eq!(canonical(&dazeus, &config, NETWORK, "my_var_"), "my_var_");
+    }
+
+    #[test]
+    fn unidentified_nicks_are_not_remembered() {
+        let mut dazeus = FakeDaZeus::new();
+        dazeus.accounts.push(("ruben_".to_string(), "mallory".to_string()));
+        let config = config();
+
+        assert_eq!(canonical_nick(&dazeus, &config, NETWORK, "ruben"), "ruben");
+        assert_eq!(canonical_nick(&dazeus, &config, NETWORK, "ruben_"), "ruben");
+        assert_eq!(canonical(&dazeus, &config, NETWORK, "ruben_"), "ruben_");
+    }
+
+    #[test]
+    fn nick_changes_are_followed_to_the_same_account() {
+        let mut dazeus = FakeDaZeus::new();
+        dazeus.accounts.push(("rvw".to_string(), "ruben".to_string()));
+        let config = config();
+
+        track_nick_change(&dazeus.nick_change("ruben", "rvw"), &dazeus, &config);
+        assert_eq!(canonical(&dazeus, &config, NETWORK, "rvw"), "ruben");
+        assert_eq!(canonical(&dazeus, &config, NETWORK, "ruben_"), "ruben");
+    }
+
+    #[test]
+    fn nick_changes_to_another_account_are_not_followed() {
+        let mut dazeus = FakeDaZeus::new();
+        dazeus.accounts.push(("ruben2".to_string(), "mallory".to_string()));
+        let config = config();
+
+        track_nick_change(&dazeus.nick_change("ruben", "ruben2"), &dazeus, &config);
+        assert_eq!(canonical(&dazeus, &config, NETWORK, "ruben2"), "ruben2");
+    }
+
+    #[test]
+    fn unidentified_nick_changes_are_not_followed() {
+        let dazeus = FakeDaZeus::new();
+        let config = config();
+
+        track_nick_change(&dazeus.nick_change("ruben", "rvw"), &dazeus, &config);
+        assert_eq!(canonical(&dazeus, &config, NETWORK, "rvw"), "rvw");
+    }
+}
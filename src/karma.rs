@@ -1,11 +1,82 @@
 use rustc_serialize::json;
 use error::KarmaError;
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, UTC};
 use dazeus::{DaZeusClient, Scope, Response};
-use std::ascii::AsciiExt;
+use config::Config;
+use history::Vote;
+use store::KarmaStore;
+use alias;
+use decay;
+use term::normalize;
 
 pub const STORE_PREFIX: &'static str = "dazeus_karma.";
 
+/// Format a time the way it is stored. Times are stored in UTC, as that is
+/// what the `Z` at the end says and how they are read back.
+pub fn format_time(time: &DateTime<Local>) -> String {
+    time.with_timezone(&UTC).format("%FT%TZ").to_string()
+}
+
+/// The version of the JSON layout `KarmaValue` is stored in. Bump it and add a
+/// step to `MIGRATIONS` whenever the layout changes, so older records can
+/// still be read.
+pub const SCHEMA_VERSION: u64 = 1;
+
+type Migration = fn(json::Object) -> Result<json::Object, Box<::std::error::Error>>;
+
+/// Upgrades a stored karma object by one version, `MIGRATIONS[n]` takes it from
+/// version `n` to `n + 1`.
+static MIGRATIONS: [Migration; 1] = [from_unversioned];
+
+/// Records without a `schema_version` may have their votes as strings, and
+/// those written before revisions were kept have none.
+fn from_unversioned(mut obj: json::Object) -> Result<json::Object, Box<::std::error::Error>> {
+    if let Some(&mut json::Json::Object(ref mut votes)) = obj.get_mut("votes") {
+        for key in ["up", "down"].iter() {
+            let count = match votes.get(*key) {
+                Some(&json::Json::String(ref s)) => match s.parse::<u64>() {
+                    Ok(n) => n,
+                    Err(_) => return Err(From::from(KarmaError::new(&format!("Invalid value for key '{}'", key)[..]))),
+                },
+                _ => continue,
+            };
+            votes.insert(key.to_string(), json::Json::U64(count));
+        }
+    }
+
+    if !obj.contains_key("revision") {
+        obj.insert("revision".to_string(), json::Json::U64(0));
+    }
+    Ok(obj)
+}
+
+/// Whether stored karma was written by a newer version of the plugin, in which
+/// case it should be left alone rather than repaired.
+pub fn is_newer_schema(s: &str) -> bool {
+    match json::Json::from_str(s) {
+        Ok(data) => data.find("schema_version").and_then(|v| v.as_u64()).map_or(false, |v| v > SCHEMA_VERSION),
+        Err(_) => false,
+    }
+}
+
+/// Bring a stored karma object up to the current schema version.
+fn migrate(mut obj: json::Object) -> Result<json::Object, Box<::std::error::Error>> {
+    let version = match obj.get("schema_version") {
+        Some(v) if v.is_u64() => v.as_u64().unwrap(),
+        None => 0,
+        _ => return Err(From::from(KarmaError::new("Invalid value for key 'schema_version'"))),
+    };
+    if version > SCHEMA_VERSION {
+        return Err(From::from(KarmaError::new(&format!("Karma was stored in schema version {}, but only up to {} is known", version, SCHEMA_VERSION)[..])));
+    }
+
+    for step in version..SCHEMA_VERSION {
+        obj = try!(MIGRATIONS[step as usize](obj));
+        obj.insert("schema_version".to_string(), json::Json::U64(step + 1));
+    }
+    Ok(obj)
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct KarmaChange {
     pub up: u64,
@@ -45,13 +116,63 @@ impl KarmaStyle {
             second
         }
     }
+
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            KarmaStyle::Notify => "notify",
+            KarmaStyle::Silent => "silent",
+            KarmaStyle::Implicit => "implicit",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<KarmaStyle> {
+        match s {
+            "notify" => Some(KarmaStyle::Notify),
+            "silent" => Some(KarmaStyle::Silent),
+            "implicit" => Some(KarmaStyle::Implicit),
+            _ => None,
+        }
+    }
+}
+
+/// The operator that was used to change karma: `term++`, `++term`, `term +1`
+/// or `term += 3`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KarmaOperator {
+    Postfix,
+    Prefix,
+    Vote,
+    Assign
+}
+
+impl KarmaOperator {
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            KarmaOperator::Postfix => "postfix",
+            KarmaOperator::Prefix => "prefix",
+            KarmaOperator::Vote => "vote",
+            KarmaOperator::Assign => "assign",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<KarmaOperator> {
+        match s {
+            "postfix" => Some(KarmaOperator::Postfix),
+            "prefix" => Some(KarmaOperator::Prefix),
+            "vote" => Some(KarmaOperator::Vote),
+            "assign" => Some(KarmaOperator::Assign),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Karma {
     pub term: String,
     pub change: KarmaChange,
-    pub style: KarmaStyle
+    pub operator: KarmaOperator,
+    pub style: KarmaStyle,
+    pub reason: Option<String>
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -60,24 +181,43 @@ pub struct KarmaValue {
     pub original_term: String,
     pub votes: KarmaChange,
     pub last_vote: DateTime<Local>,
-    pub first_vote: DateTime<Local>
+    pub first_vote: DateTime<Local>,
+    /// The karma after decay, only known when decay is enabled.
+    pub current: Option<f64>,
+    /// Bumped whenever the karma is stored outright, so stores that count votes
+    /// separately know which counts were made on top of it.
+    pub revision: u64
 }
 
 impl KarmaValue {
     pub fn new(term: &str) -> KarmaValue {
         KarmaValue {
-            term: term.to_ascii_lowercase(),
+            term: normalize(term),
             original_term: term.to_string(),
             votes: KarmaChange::new(0, 0),
             last_vote: Local::now(),
-            first_vote: Local::now()
+            first_vote: Local::now(),
+            current: None,
+            revision: 0
         }
     }
 
-    pub fn vote(&mut self, karma: &Karma) {
-        self.last_vote = Local::now();
-        self.votes.up += karma.change.up;
-        self.votes.down += karma.change.down;
+    pub fn vote(&mut self, vote: &Vote) {
+        self.last_vote = vote.time;
+        self.votes.up += vote.change.up;
+        self.votes.down += vote.change.down;
+    }
+
+    /// Fold the votes of another term into this one.
+    pub fn merge(&mut self, other: &KarmaValue) {
+        self.votes.up += other.votes.up;
+        self.votes.down += other.votes.down;
+        if other.first_vote < self.first_vote {
+            self.first_vote = other.first_vote;
+        }
+        if other.last_vote > self.last_vote {
+            self.last_vote = other.last_vote;
+        }
     }
 
     pub fn from_json(data: json::Json) -> Result<KarmaValue, Box<::std::error::Error>> {
@@ -88,40 +228,65 @@ impl KarmaValue {
             });
         }
 
-        if data.is_object() {
-            let obj = data.as_object().unwrap();
+        if let json::Json::Object(obj) = data {
+            let obj = try!(migrate(obj));
             let term = get_key!(obj, "term", is_string, as_string);
             let first_vote_str = get_key!(obj, "first_vote", is_string, as_string);
             let last_vote_str = get_key!(obj, "last_vote", is_string, as_string);
             let votes = get_key!(obj, "votes", is_object, as_object);
 
-            let upvotes = match votes.get("up") {
-                Some(m) if m.is_u64() => m.as_u64().unwrap(),
-                Some(m) if m.is_string() && m.as_string().unwrap().parse::<u64>().is_ok() => m.as_string().unwrap().parse().unwrap(),
-                _ => return Err(From::from(KarmaError::new("No value or invalid value for key 'up'")))
-            };
-
-            let downvotes = match votes.get("down") {
-                Some(m) if m.is_u64() => m.as_u64().unwrap(),
-                Some(m) if m.is_string() && m.as_string().unwrap().parse::<u64>().is_ok() => m.as_string().unwrap().parse().unwrap(),
-                _ => return Err(From::from(KarmaError::new("No value or invalid value for key 'down'")))
-            };
+            let upvotes = get_key!(votes, "up", is_u64, as_u64);
+            let downvotes = get_key!(votes, "down", is_u64, as_u64);
+            let revision = get_key!(obj, "revision", is_u64, as_u64);
 
             let first_vote = try!(first_vote_str.parse::<DateTime<Local>>());
             let last_vote = try!(last_vote_str.parse::<DateTime<Local>>());
 
             Ok(KarmaValue {
-                term: term.to_ascii_lowercase(),
+                term: normalize(term),
                 original_term: term.to_string(),
                 votes: KarmaChange::new(upvotes, downvotes),
                 last_vote: last_vote,
-                first_vote: first_vote
+                first_vote: first_vote,
+                current: None,
+                revision: revision
             })
         } else {
             Err(From::from(KarmaError::new("Invalid json: not an object")))
         }
     }
 
+    /// Make the best of stored karma that can't be read: keep the votes and
+    /// dates that still make sense and start over for the rest.
+    pub fn salvage(term: &str, s: &str) -> KarmaValue {
+        let mut karma = KarmaValue::new(term);
+        let data = match json::Json::from_str(s) {
+            Ok(data) => data,
+            Err(_) => return karma,
+        };
+
+        let count = |key: &str| -> u64 {
+            match data.find_path(&["votes", key]) {
+                Some(m) if m.is_u64() => m.as_u64().unwrap(),
+                Some(m) if m.is_string() => m.as_string().unwrap().parse().unwrap_or(0),
+                _ => 0,
+            }
+        };
+        let time = |key: &str| -> Option<DateTime<Local>> {
+            data.find(key).and_then(|t| t.as_string()).and_then(|t| t.parse::<DateTime<Local>>().ok())
+        };
+
+        karma.votes = KarmaChange::new(count("up"), count("down"));
+        if let Some(first_vote) = time("first_vote") {
+            karma.first_vote = first_vote;
+        }
+        if let Some(last_vote) = time("last_vote") {
+            karma.last_vote = last_vote;
+        }
+        karma.revision = data.find("revision").and_then(|r| r.as_u64()).unwrap_or(0);
+        karma
+    }
+
     pub fn from_str(s: &str) -> Result<KarmaValue, Box<::std::error::Error>> {
         let data = try!(json::Json::from_str(s));
         let karma = KarmaValue::from_json(data);
@@ -141,20 +306,46 @@ impl KarmaValue {
         }
     }
 
-    pub fn from_dazeus(dazeus: &DaZeusClient, scope: Scope, term: &str) -> Result<KarmaValue, Box<::std::error::Error>> {
-        let property = format!("{}{}", STORE_PREFIX, term.to_ascii_lowercase());
-
-        let mut karma = KarmaValue::from_response(&dazeus.get_property(&property[..], scope));
-        if let Ok(ref mut k) = karma {
+    /// Look up the karma of a term, following aliases and applying decay.
+    /// Returns `None` for terms that never got any karma.
+    pub fn load(store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope, config: &Config, term: &str) -> Result<Option<KarmaValue>, Box<::std::error::Error>> {
+        let resolved = alias::resolve(store, dazeus, scope.clone(), term);
+        let mut karma = try!(store.get(dazeus, scope.clone(), &resolved[..]));
+        if let Some(ref mut k) = karma {
             k.original_term = term.to_string();
+            decay::apply_decay(k, store, dazeus, scope, config);
+        }
+        Ok(karma)
+    }
+
+    /// The karma used to compare terms: the current karma if decay is enabled,
+    /// or the total karma otherwise.
+    pub fn score(&self) -> f64 {
+        match self.current {
+            Some(c) => (c * 10.0).round() / 10.0,
+            None => self.votes.total() as f64,
+        }
+    }
+
+    pub fn score_to_string(&self) -> String {
+        match self.current {
+            Some(_) => format!("{:.1}", self.score()),
+            None => self.votes.total().to_string(),
+        }
+    }
+
+    pub fn karma_to_string(&self) -> String {
+        match self.current {
+            Some(_) => format!("{}, currently {}", self.votes.to_string(), self.score_to_string()),
+            None => self.votes.to_string(),
         }
-        karma
     }
 
     pub fn to_string(&self) -> String {
-        match self.votes.total() {
-            0 => format!("{} has neutral karma (+{}, -{})", self.original_term, self.votes.up, self.votes.down),
-            _ => format!("{} has a karma of {}", self.original_term, self.votes.to_string()),
+        match (self.votes.total(), self.current) {
+            (0, None) => format!("{} has neutral karma (+{}, -{})", self.original_term, self.votes.up, self.votes.down),
+            (0, Some(_)) => format!("{} has neutral karma (+{}, -{}), currently {}", self.original_term, self.votes.up, self.votes.down, self.score_to_string()),
+            _ => format!("{} has a karma of {}", self.original_term, self.karma_to_string()),
         }
     }
 }
@@ -162,6 +353,7 @@ impl KarmaValue {
 impl json::ToJson for KarmaValue {
     fn to_json(&self) -> json::Json {
         let mut obj = json::Object::new();
+        obj.insert("schema_version".to_string(), SCHEMA_VERSION.to_json());
         obj.insert("term".to_string(), self.term.to_json());
 
         let mut votes = json::Object::new();
@@ -169,9 +361,67 @@ impl json::ToJson for KarmaValue {
         votes.insert("down".to_string(), self.votes.down.to_json());
 
         obj.insert("votes".to_string(), votes.to_json());
-        obj.insert("first_vote".to_string(), self.first_vote.format("%FT%TZ").to_string().to_json());
-        obj.insert("last_vote".to_string(), self.last_vote.format("%FT%TZ").to_string().to_json());
+        obj.insert("first_vote".to_string(), format_time(&self.first_vote).to_json());
+        obj.insert("last_vote".to_string(), format_time(&self.last_vote).to_json());
+        obj.insert("revision".to_string(), self.revision.to_json());
 
         obj.to_json()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_serialize::json::{self, ToJson};
+
+    #[test]
+    fn unversioned_string_counts_are_migrated() {
+        let karma = KarmaValue::from_str(r#"{"term": "foo", "votes": {"up": "3", "down": "1"},
+            "first_vote": "2015-01-01T10:00:00Z", "last_vote": "2015-02-01T10:00:00Z", "revision": 4}"#).unwrap();
+        assert_eq!((karma.votes.up, karma.votes.down), (3, 1));
+        assert_eq!(karma.revision, 4);
+
+        assert!(KarmaValue::from_str(r#"{"term": "foo", "votes": {"up": "lots", "down": "1"},
+            "first_vote": "2015-01-01T10:00:00Z", "last_vote": "2015-02-01T10:00:00Z"}"#).is_err());
+    }
+
+    #[test]
+    fn unversioned_records_without_a_revision_start_at_zero() {
+        let karma = KarmaValue::from_str(r#"{"term": "foo", "votes": {"up": 2, "down": 5},
+            "first_vote": "2015-01-01T10:00:00Z", "last_vote": "2015-02-01T10:00:00Z"}"#).unwrap();
+        assert_eq!((karma.votes.up, karma.votes.down), (2, 5));
+        assert_eq!(karma.revision, 0);
+    }
+
+    #[test]
+    fn current_records_read_back_the_same() {
+        let mut karma = KarmaValue::new("foo bar");
+        karma.votes = KarmaChange::new(7, 2);
+        karma.revision = 12;
+
+        let stored = karma.to_json();
+        assert_eq!(stored.find("schema_version").and_then(|v| v.as_u64()), Some(SCHEMA_VERSION));
+        let read = KarmaValue::from_str(&stored.to_string()[..]).unwrap();
+        assert_eq!(read.term, karma.term);
+        assert_eq!((read.votes.up, read.votes.down), (7, 2));
+        assert_eq!(read.revision, 12);
+        assert_eq!(read.first_vote.timestamp(), karma.first_vote.timestamp());
+        assert_eq!(read.last_vote.timestamp(), karma.last_vote.timestamp());
+        assert_eq!(read.to_json(), stored);
+    }
+
+    #[test]
+    fn newer_records_are_refused() {
+        let mut stored = match KarmaValue::new("foo").to_json() {
+            json::Json::Object(obj) => obj,
+            _ => unreachable!(),
+        };
+        stored.insert("schema_version".to_string(), (SCHEMA_VERSION + 1).to_json());
+        let stored = stored.to_json().to_string();
+
+        assert!(is_newer_schema(&stored[..]));
+        let error = KarmaValue::from_str(&stored[..]).unwrap_err();
+        assert!(error.to_string().contains("schema version 2"));
+        assert!(!is_newer_schema(&KarmaValue::new("foo").to_json().to_string()[..]));
+    }
+}
@@ -5,15 +5,43 @@ extern crate dazeus;
 extern crate docopt;
 extern crate rustc_serialize;
 extern crate chrono;
+extern crate toml;
+extern crate unicode_normalization;
+extern crate regex;
+extern crate rusqlite;
 
 use docopt::Docopt;
 use dazeus::{DaZeus, DaZeusClient, EventType, Connection};
 use handler::*;
+use admin::*;
+use config::Config;
+use ratelimit::RateLimiter;
+use switch::Feature;
+use store::{DaZeusStore, KarmaStore, MemoryStore};
+use sqlite::SqliteStore;
+use std::rc::Rc;
 
 mod karma;
-mod grammar;
+mod parser;
+mod term;
 mod handler;
+mod history;
 mod error;
+mod config;
+mod ratelimit;
+mod alias;
+mod admin;
+mod decay;
+mod trend;
+mod reason;
+mod identity;
+mod filter;
+mod switch;
+mod store;
+mod sqlite;
+mod migrate;
+#[cfg(test)]
+mod fake;
 
 // Write the Docopt usage string.
 static USAGE: &'static str = "
@@ -21,9 +49,18 @@ The DaZeus karma plugin.
 
 Usage:
     dazeus-karma [options]
+    dazeus-karma [options] --migrate-keys
 
 Options:
     -h, --help                  Show this help message
+    -c FILE, --config=FILE      Read the plugin configuration from a TOML file
+    --migrate-keys              Merge stored terms that only differ in case,
+                                Unicode normalization or whitespace, then exit
+    --store=STORE               Where to keep karma: `dazeus` (as DaZeus
+                                properties), `sqlite` or `memory` (not kept
+                                after the plugin stops) [default: dazeus]
+    --database=FILE             The SQLite database to use with `--store=sqlite`
+                                [default: karma.sqlite]
     -s SOCKET, --socket=SOCKET  Specify the socket DaZeus is listening to, use
                                 `unix:/path/to/socket` or `tcp:host:port`
                                 [default: unix:/tmp/dazeus.sock]
@@ -34,15 +71,49 @@ fn main() {
 
     let args = Docopt::new(USAGE).and_then(|d| d.parse()).unwrap_or_else(|e| e.exit());
     let socket = args.get_str("--socket");
+    let config_file = match args.get_str("--config") {
+        "" => None,
+        path => Some(path),
+    };
 
     let connection = Connection::from_str(socket).unwrap();
     let mut dazeus = DaZeus::new(connection);
 
-    dazeus.handshake("dazeus-karma", "1", None);
+    dazeus.handshake("dazeus-karma", "1", Some("karma"));
+    let config = Config::load(&dazeus, config_file).unwrap_or_else(|e| {
+        error!("Could not load configuration: {}", e);
+        ::std::process::exit(1);
+    });
+
+    if args.get_bool("--migrate-keys") {
+        let mut moved = 0;
+        for scope in migrate::migration_scopes(&dazeus, &config) {
+            moved += migrate::normalize_keys(&dazeus, scope, &config);
+        }
+        println!("Moved {} terms to their normalized key", moved);
+        return;
+    }
+
+    let store: Rc<KarmaStore> = match args.get_str("--store") {
+        "dazeus" => Rc::new(DaZeusStore::new(&config)),
+        "sqlite" => Rc::new(SqliteStore::open(args.get_str("--database")).unwrap_or_else(|e| {
+            error!("Could not open the karma database: {}", e);
+            ::std::process::exit(1);
+        })),
+        "memory" => Rc::new(MemoryStore::new()),
+        other => {
+            error!("Unknown store '{}', use `dazeus`, `sqlite` or `memory`", other);
+            ::std::process::exit(1);
+        }
+    };
 
-    dazeus.subscribe(EventType::PrivMsg, |evt, dazeus| {
-        let highlight_char = dazeus.get_highlight_char().unwrap_or("}".to_string());
-        let nick = dazeus.nick(&evt[0]).unwrap_or("DaZeus".to_string());
+    let mut limiter = RateLimiter::new(&config);
+
+    let karma_config = config.clone();
+    let karma_store = store.clone();
+    dazeus.subscribe(EventType::PrivMsg, move |evt, dazeus| {
+        let highlight_char = dazeus.get_highlight_char().unwrap_or(karma_config.highlight_char.clone());
+        let nick = dazeus.nick(&evt[0]).unwrap_or(karma_config.nick.clone());
 
         let hl_with_char = format!("{}karma", highlight_char);
         let hl_with_nick = format!("{}:", nick);
@@ -51,17 +122,62 @@ fn main() {
         let msg = &evt[3];
 
         if !msg.starts_with(&hl_with_char[..]) && !msg.starts_with(&hl_with_nick[..]) && !msg.starts_with(&hl_with_nick_alt[..]) {
-            handle_karma_events(&evt, dazeus);
+            handle_karma_events(&evt, dazeus, &karma_config, &*karma_store, &mut limiter);
         }
     });
 
-    dazeus.subscribe_command("karma", |evt, dazeus| {
-        reply_to_karma_command(&evt, dazeus);
+    if config.identities {
+        let identity_config = config.clone();
+        dazeus.subscribe(EventType::Nick, move |evt, dazeus| {
+            identity::track_nick_change(&evt, dazeus, &identity_config);
+        });
+    }
+
+    macro_rules! subscribe_command {
+        ($command:expr, $handler:ident) => ({
+            let config = config.clone();
+            dazeus.subscribe_command($command, move |evt, dazeus| {
+                if switch::is_enabled(dazeus, &config, &evt[0], &evt[2], Feature::Commands) {
+                    $handler(&evt, dazeus, &config);
+                }
+            });
+        });
+        ($command:expr, $handler:ident, store) => ({
+            let config = config.clone();
+            let store = store.clone();
+            dazeus.subscribe_command($command, move |evt, dazeus| {
+                if switch::is_enabled(dazeus, &config, &evt[0], &evt[2], Feature::Commands) {
+                    $handler(&evt, dazeus, &config, &*store);
+                }
+            });
+        });
+    }
+
+    // `}karma on` has to keep working after commands were turned off
+    let switch_config = config.clone();
+    let switch_store = store.clone();
+    dazeus.subscribe_command("karma", move |evt, dazeus| {
+        if switch::is_switch_command(&evt, dazeus, &switch_config) {
+            switch::reply_to_karma_switch_command(&evt, dazeus, &switch_config);
+        } else if switch::is_enabled(dazeus, &switch_config, &evt[0], &evt[2], Feature::Commands) {
+            reply_to_karma_command(&evt, dazeus, &switch_config, &*switch_store);
+        }
     });
 
-    dazeus.subscribe_command("karmafight", |evt, dazeus| {
-        reply_to_karmafight_command(&evt, dazeus);
-    });
+    subscribe_command!("karmafight", reply_to_karmafight_command, store);
+    subscribe_command!("karmawho", reply_to_karmawho_command, store);
+    subscribe_command!("karmatrend", reply_to_karmatrend_command, store);
+    subscribe_command!("karmawhy", reply_to_karmawhy_command, store);
+    subscribe_command!("karmatop", reply_to_karmatop_command, store);
+    subscribe_command!("karmabottom", reply_to_karmabottom_command, store);
+    subscribe_command!("karmaalias", reply_to_karmaalias_command, store);
+    subscribe_command!("karmamerge", reply_to_karmamerge_command, store);
+    subscribe_command!("karmaset", reply_to_karmaset_command, store);
+    subscribe_command!("karmareset", reply_to_karmareset_command, store);
+    subscribe_command!("karmadelete", reply_to_karmadelete_command, store);
+    subscribe_command!("karmafsck", reply_to_karmafsck_command, store);
+    subscribe_command!("karmadeny", reply_to_karmadeny_command, store);
+    subscribe_command!("karmaallow", reply_to_karmaallow_command, store);
 
     dazeus.listen().unwrap();
 }
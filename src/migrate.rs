@@ -0,0 +1,185 @@
+use dazeus::{DaZeusClient, Scope};
+use config::{Config, ScopeMode};
+use history::VoteHistory;
+use karma::KarmaValue;
+use reason::Reasons;
+use store;
+use term::normalize;
+use trend::Trend;
+use rustc_serialize::json::ToJson;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// All scopes karma may have been stored in with the current configuration.
+///
+/// DaZeus only lists the properties of a scope we name, so channels are looked
+/// for in the channels the store listed, the channels votes in the stored
+/// histories were cast in and the channels we're in. Only those that actually
+/// have karma properties are returned.
+pub fn migration_scopes(dazeus: &DaZeusClient, config: &Config) -> Vec<Scope> {
+    let mut scopes = Vec::new();
+    if config.scope == ScopeMode::Global {
+        scopes.push(Scope::any());
+    }
+
+    for network in dazeus.networks() {
+        if config.scope == ScopeMode::Network || config.scope == ScopeMode::ChannelRollup {
+            scopes.push(Scope::network(&network[..]));
+        }
+        if config.scope == ScopeMode::Channel || config.scope == ScopeMode::ChannelRollup {
+            let mut channels = store::stored_channels(dazeus, config, &network[..]);
+            channels.extend(history_channels(dazeus, config, Scope::network(&network[..])).into_iter());
+            channels.extend(dazeus.channels(&network[..]).into_iter());
+            channels.sort();
+            channels.dedup();
+
+            for channel in channels {
+                let scope = Scope::target(&network[..], &channel[..]);
+                if has_karma_properties(dazeus, config, scope.clone()) {
+                    scopes.push(scope);
+                }
+            }
+        }
+    }
+    scopes
+}
+
+/// The channels in which the votes stored in a scope were cast.
+fn history_channels(dazeus: &DaZeusClient, config: &Config, scope: Scope) -> Vec<String> {
+    let mut channels = Vec::new();
+    for prefix in vec!(config.history_prefix(), config.votes_prefix()) {
+        for key in dazeus.get_property_keys(&prefix[..], scope.clone()) {
+            let term = if key.starts_with(&prefix[..]) { &key[prefix.len()..] } else { &key[..] };
+            let response = dazeus.get_property(&format!("{}{}", prefix, term)[..], scope.clone());
+            if let Some(Ok(history)) = response.get_str("value").map(|s| VoteHistory::from_str(term, s)) {
+                channels.extend(history.votes.into_iter().map(|v| v.channel));
+            }
+        }
+    }
+    channels
+}
+
+fn has_karma_properties(dazeus: &DaZeusClient, config: &Config, scope: Scope) -> bool {
+    dazeus.get_property_keys(config.store_prefix.trim_right_matches('.'), scope).len() > 0
+}
+
+/// Move everything that is stored under a key that isn't normalized to its
+/// normalized key, merging terms that turn out to be the same. Returns the
+/// number of terms that were moved.
+pub fn normalize_keys(dazeus: &DaZeusClient, scope: Scope, config: &Config) -> usize {
+    let karma_moved = migrate_prefix(dazeus, scope.clone(), &config.store_prefix[..], |term, values| {
+        let mut merged = try!(KarmaValue::from_str(values[0]));
+        for value in values[1..].iter() {
+            merged.merge(&try!(KarmaValue::from_str(value)));
+        }
+        merged.term = term.to_string();
+        Ok(merged.to_json().to_string())
+    });
+
+    migrate_prefix(dazeus, scope.clone(), &config.history_prefix()[..], |term, values| {
+        let mut merged = VoteHistory::new(term);
+        for value in values.iter() {
+            merged.merge(&try!(VoteHistory::from_str(term, value)));
+        }
+        Ok(merged.votes.to_json().to_string())
+    });
+
+    migrate_prefix(dazeus, scope.clone(), &config.trend_prefix()[..], |term, values| {
+        let mut merged = Trend::new(term);
+        for value in values.iter() {
+            merged.merge(&try!(Trend::from_str(term, value)));
+        }
+        Ok(merged.to_json().to_string())
+    });
+
+    migrate_prefix(dazeus, scope.clone(), &config.reasons_prefix()[..], |term, values| {
+        let mut merged = Reasons::new(term);
+        for value in values.iter() {
+            merged.merge(&try!(Reasons::from_str(term, value)));
+        }
+        Ok(merged.reasons.to_json().to_string())
+    });
+
+    // when aliases collide, one of them wins
+    migrate_prefix(dazeus, scope, &config.alias_prefix()[..], |_, values| {
+        Ok(normalize(values[0]))
+    });
+
+    karma_moved
+}
+
+/// Group all properties starting with `prefix` by their normalized key and
+/// replace every group that contains an unnormalized key by a single property
+/// with the value produced by `merge`.
+fn migrate_prefix<F>(dazeus: &DaZeusClient, scope: Scope, prefix: &str, merge: F) -> usize
+    where F: Fn(&str, &[&str]) -> Result<String, Box<Error>> {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for key in dazeus.get_property_keys(prefix, scope.clone()) {
+        let term = if key.starts_with(prefix) { key[prefix.len()..].to_string() } else { key };
+        groups.entry(normalize(&term[..])).or_insert(Vec::new()).push(term);
+    }
+
+    let mut moved = 0;
+    for (term, raw_terms) in groups.iter() {
+        if raw_terms.iter().all(|t| t == term) {
+            continue;
+        }
+
+        let mut values = Vec::new();
+        for raw in raw_terms.iter() {
+            let response = dazeus.get_property(&format!("{}{}", prefix, raw)[..], scope.clone());
+            if let Some(s) = response.get_str("value") {
+                values.push(s.to_string());
+            }
+        }
+        if values.len() == 0 {
+            continue;
+        }
+
+        let value_refs = values.iter().map(|v| &v[..]).collect::<Vec<&str>>();
+        match merge(&term[..], &value_refs[..]) {
+            Ok(value) => {
+                dazeus.set_property(&format!("{}{}", prefix, term)[..], &value[..], scope.clone());
+                for raw in raw_terms.iter().filter(|t| *t != term) {
+                    info!("Moved '{}{}' to '{}{}'", prefix, raw, prefix, term);
+                    dazeus.unset_property(&format!("{}{}", prefix, raw)[..], scope.clone());
+                    moved += 1;
+                }
+            },
+            Err(e) => warn!("Could not merge {} into '{}{}', leaving them as they are: {}", raw_terms.connect(", "), prefix, term, e),
+        }
+    }
+    moved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::migration_scopes;
+    use config::{Config, ScopeMode};
+    use dazeus::Scope;
+    use fake::{FakeDaZeus, NETWORK};
+    use history::Vote;
+    use karma::{Karma, KarmaChange, KarmaOperator, KarmaStyle};
+    use store::{DaZeusStore, KarmaStore};
+
+    #[test]
+    fn channels_we_left_are_migrated_as_well() {
+        let dazeus = FakeDaZeus::new();
+        let mut config = Config::new();
+        config.scope = ScopeMode::Channel;
+        let store = DaZeusStore::new(&config);
+        let vote = Vote::new("alice", "#left", &Karma {
+            term: "Foo".to_string(),
+            change: KarmaChange::new(1, 0),
+            operator: KarmaOperator::Postfix,
+            style: KarmaStyle::Notify,
+            reason: None
+        });
+        store.apply_vote(&dazeus, Scope::target(NETWORK, "#left"), &vote).unwrap();
+
+        // the channel we're in has no karma, so there is nothing to migrate there
+        let scopes = migration_scopes(&dazeus, &config);
+        assert_eq!(scopes.len(), 1);
+        assert_eq!(scopes[0].target, Some("#left".to_string()));
+    }
+}
@@ -0,0 +1,644 @@
+//! Finds karma changes in chat messages.
+//!
+//! The parser is a hand-written recursive descent parser for the following
+//! grammar. Every rule is implemented by the method of the same name, and just
+//! like a PEG parser it takes the first alternative that matches.
+//!
+//! ```text
+//! line           = (code_span / url / karmachange / .)*
+//! code_span      = "`" [^`]* "`"
+//! url            = [a-zA-Z] [a-zA-Z0-9+.-]* "://" (!whitespace .)*
+//!                / "www." (!whitespace .)*
+//! karmachange    = "[" [^][]* "]" modifier reason?
+//!                / "(" [^)(]* ")" modifier reason?
+//!                / implicit_chars modifier reason?
+//!                / prefix_modifier implicit_chars at_boundary reason?
+//! implicit_chars = term_unit ("-"? term_unit)*
+//! term_unit      = term_char term_mark*
+//! modifier       = ("++" / "--") at_boundary
+//!                / spacing* ("+=" / "-=") spacing* number at_boundary
+//!                / spacing+ ("+1" / "-1") at_boundary
+//! prefix_modifier = "++" / "--"
+//! reason         = spacing* ("#" / "for" &spacing / "because" &spacing) spacing* !karmachange reason_text
+//! reason_text    = (!reason_end .)*
+//! reason_end     = [,;] spacing* karmachange / whitespace karmachange
+//! number         = [0-9]+
+//! spacing        = [ \t]
+//! at_boundary    = &whitespace / &[,.;:)] / !.
+//! ```
+//!
+//! The prefix form only counts at the start of a word, and the markers of a
+//! reason are matched without regard to case. Anything inside a code span or
+//! URL is skipped, so neither `` `i++` `` nor `https://example.com/c++-faq`
+//! count as a vote.
+use karma::{Karma, KarmaChange, KarmaOperator, KarmaStyle};
+use error::KarmaError;
+use term::{is_boundary_before, is_term_char, is_term_mark};
+use std::ascii::AsciiExt;
+use std::cell::{Cell, RefCell};
+
+/// What went wrong while parsing a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// Karma given to nothing, like `[]++` or `( )--`.
+    EmptyTerm,
+    /// The number after `+=` or `-=` doesn't fit in a vote.
+    NumberTooLarge
+}
+
+impl ParseErrorKind {
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            ParseErrorKind::EmptyTerm => "empty term",
+            ParseErrorKind::NumberTooLarge => "number too large",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// The byte offset in the message the error was found at.
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    pub expected: Vec<&'static str>
+}
+
+impl ParseError {
+    fn new(input: &str, kind: ParseErrorKind, offset: usize, expected: Vec<&'static str>) -> ParseError {
+        let (line, column) = pos_to_line(input, offset);
+        ParseError { kind: kind, offset: offset, line: line, column: column, expected: expected }
+    }
+}
+
+impl ::std::fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        try!(write!(fmt, "{} at {}:{}", self.kind.to_str(), self.line, self.column));
+        let expected = self.expected.iter().map(|e| format!("`{}`", escape_default(e))).collect::<Vec<String>>();
+        match expected.len() {
+            0 => Ok(()),
+            1 => write!(fmt, ", expected {}", expected[0]),
+            _ => write!(fmt, ", expected one of {}", expected.connect(", ")),
+        }
+    }
+}
+
+impl ::std::error::Error for ParseError {
+    fn description(&self) -> &str {
+        self.kind.to_str()
+    }
+}
+
+/// The karma changes found in a line of chat, and the karma changes in it we
+/// couldn't make sense of.
+#[derive(Debug)]
+pub struct ParsedLine {
+    pub changes: Vec<Karma>,
+    /// Every error carries the `ParseError` describing the problem.
+    pub errors: Vec<KarmaError>
+}
+
+/// Find all karma changes in a line of chat. A karma change we can't make sense
+/// of is reported, but doesn't stop the others in the message from counting.
+pub fn line(input: &str) -> ParsedLine {
+    let parser = Parser { input: input, errors: RefCell::new(Vec::new()), quiet: Cell::new(0) };
+    let mut changes = Vec::new();
+    let mut pos = 0;
+    while let Some((next, change)) = parser.element(pos) {
+        if let Some(change) = change {
+            changes.push(change);
+        }
+        pos = next;
+    }
+
+    ParsedLine {
+        changes: changes,
+        errors: parser.errors.into_inner().into_iter().map(From::from).collect()
+    }
+}
+
+/// Every rule takes the position to start matching at and returns the
+/// position after the match along with its value, or `None` if it doesn't match.
+struct Parser<'a> {
+    input: &'a str,
+    errors: RefCell<Vec<ParseError>>,
+    /// Errors aren't reported while this is above zero.
+    quiet: Cell<usize>
+}
+
+impl<'a> Parser<'a> {
+    /// Remember an error. Looking ahead may run into the same problem more
+    /// than once, it is only reported the first time.
+    fn fail(&self, kind: ParseErrorKind, offset: usize, expected: Vec<&'static str>) {
+        if self.quiet.get() > 0 {
+            return;
+        }
+        let mut errors = self.errors.borrow_mut();
+        if !errors.iter().any(|e| e.kind == kind && e.offset == offset) {
+            errors.push(ParseError::new(self.input, kind, offset, expected));
+        }
+    }
+
+    /// Run a rule without reporting errors. Used to check whether the text of
+    /// a reason ends, as that text is never parsed for karma changes.
+    fn quietly<T, F>(&self, rule: F) -> T where F: FnOnce() -> T {
+        self.quiet.set(self.quiet.get() + 1);
+        let result = rule();
+        self.quiet.set(self.quiet.get() - 1);
+        result
+    }
+
+    fn element(&self, pos: usize) -> Option<(usize, Option<Karma>)> {
+        if let Some(pos) = self.code_span(pos).or_else(|| self.url(pos)) {
+            return Some((pos, None));
+        }
+
+        match self.karmachange(pos) {
+            Some((pos, karma)) => Some((pos, Some(karma))),
+            None => self.any_char(pos).map(|(pos, _)| (pos, None)),
+        }
+    }
+
+    fn code_span(&self, pos: usize) -> Option<usize> {
+        self.literal(pos, '`')
+            .map(|pos| self.skip_while(pos, |c| c != '`'))
+            .and_then(|pos| self.literal(pos, '`'))
+    }
+
+    fn url(&self, pos: usize) -> Option<usize> {
+        let scheme = self.char_if(pos, is_ascii_letter)
+            .map(|pos| self.skip_while(pos, |c| is_ascii_letter(c) || (c >= '0' && c <= '9') || "+.-".contains(c)))
+            .and_then(|pos| self.literal_str(pos, "://"));
+        scheme.or_else(|| self.literal_str_case_insensitive(pos, "www."))
+            .map(|pos| self.skip_while(pos, |c| !c.is_whitespace()))
+    }
+
+    fn karmachange(&self, pos: usize) -> Option<(usize, Karma)> {
+        self.explicit_karma_change(pos, '[', ']', KarmaStyle::Notify)
+            .or_else(|| self.explicit_karma_change(pos, '(', ')', KarmaStyle::Silent))
+            .or_else(|| self.implicit_karma_change(pos))
+            .or_else(|| self.prefix_karma_change(pos))
+    }
+
+    fn explicit_karma_change(&self, pos: usize, open: char, close: char, style: KarmaStyle) -> Option<(usize, Karma)> {
+        let pos = match self.literal(pos, open) {
+            Some(pos) => pos,
+            None => return None,
+        };
+        let end = self.skip_while(pos, |c| c != open && c != close);
+        let term = &self.input[pos..end];
+        let pos = match self.literal(end, close) {
+            Some(pos) => pos,
+            None => return None,
+        };
+        let (pos, (change, operator)) = match self.modifier(pos) {
+            Some(res) => res,
+            None => return None,
+        };
+        if term.trim().len() == 0 {
+            self.fail(ParseErrorKind::EmptyTerm, end, vec!("term"));
+            return None;
+        }
+
+        let (pos, reason) = self.optional_reason(pos);
+        Some((pos, Karma { term: term.to_string(), change: change, operator: operator, style: style, reason: reason }))
+    }
+
+    fn implicit_karma_change(&self, pos: usize) -> Option<(usize, Karma)> {
+        let (pos, term) = match self.implicit_chars(pos) {
+            Some(res) => res,
+            None => return None,
+        };
+        self.modifier(pos).map(|(pos, (change, operator))| {
+            let (pos, reason) = self.optional_reason(pos);
+            (pos, Karma { term: term.to_string(), change: change, operator: operator, style: KarmaStyle::Implicit, reason: reason })
+        })
+    }
+
+    fn prefix_karma_change(&self, start: usize) -> Option<(usize, Karma)> {
+        if !is_boundary_before(self.input, start) {
+            return None;
+        }
+
+        let (pos, change) = match self.prefix_modifier(start) {
+            Some(res) => res,
+            None => return None,
+        };
+        let (pos, term) = match self.implicit_chars(pos) {
+            Some(res) => res,
+            None => return None,
+        };
+        if !self.at_boundary(pos) {
+            return None;
+        }
+
+        let (pos, reason) = self.optional_reason(pos);
+        Some((pos, Karma { term: term.to_string(), change: change, operator: KarmaOperator::Prefix, style: KarmaStyle::Implicit, reason: reason }))
+    }
+
+    fn implicit_chars(&self, start: usize) -> Option<(usize, &'a str)> {
+        let mut pos = match self.term_unit(start) {
+            Some(pos) => pos,
+            None => return None,
+        };
+
+        loop {
+            let next = self.term_unit(pos)
+                .or_else(|| self.literal(pos, '-').and_then(|p| self.term_unit(p)));
+            match next {
+                Some(next) => pos = next,
+                None => return Some((pos, &self.input[start..pos])),
+            }
+        }
+    }
+
+    fn term_unit(&self, pos: usize) -> Option<usize> {
+        self.char_if(pos, is_term_char).map(|pos| self.skip_while(pos, is_term_mark))
+    }
+
+    fn modifier(&self, start: usize) -> Option<(usize, (KarmaChange, KarmaOperator))> {
+        // `++` and `--` are the same in front of and after a term
+        if let Some((pos, change)) = self.prefix_modifier(start) {
+            if self.at_boundary(pos) {
+                return Some((pos, (change, KarmaOperator::Postfix)));
+            }
+        }
+
+        let spaced = self.skip_while(start, is_spacing);
+        for &(op, up) in [("+=", true), ("-=", false)].iter() {
+            let pos = match self.literal_str(spaced, op) {
+                Some(pos) => self.skip_while(pos, is_spacing),
+                None => continue,
+            };
+            if let Some((pos, n)) = self.number(pos) {
+                if self.at_boundary(pos) {
+                    let change = if up { KarmaChange::new(n, 0) } else { KarmaChange::new(0, n) };
+                    return Some((pos, (change, KarmaOperator::Assign)));
+                }
+            }
+        }
+
+        if spaced > start {
+            for &(op, change) in [("+1", KarmaChange::new(1, 0)), ("-1", KarmaChange::new(0, 1))].iter() {
+                if let Some(pos) = self.literal_str(spaced, op) {
+                    if self.at_boundary(pos) {
+                        return Some((pos, (change, KarmaOperator::Vote)));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn prefix_modifier(&self, pos: usize) -> Option<(usize, KarmaChange)> {
+        self.literal_str(pos, "++").map(|pos| (pos, KarmaChange::new(1, 0)))
+            .or_else(|| self.literal_str(pos, "--").map(|pos| (pos, KarmaChange::new(0, 1))))
+    }
+
+    /// A reason is optional, so this always matches.
+    fn optional_reason(&self, pos: usize) -> (usize, Option<String>) {
+        match self.reason(pos) {
+            Some((pos, reason)) => (pos, Some(reason)),
+            None => (pos, None),
+        }
+    }
+
+    fn reason(&self, pos: usize) -> Option<(usize, String)> {
+        let pos = self.skip_while(pos, is_spacing);
+        let pos = match self.reason_marker(pos) {
+            Some(pos) => self.skip_while(pos, is_spacing),
+            None => return None,
+        };
+        // in `foo++ for bar++` the reason is left out, not the vote on bar
+        if self.quietly(|| self.karmachange(pos)).is_some() {
+            return None;
+        }
+
+        let mut end = pos;
+        while !self.reason_end(end) {
+            match self.any_char(end) {
+                Some((next, _)) => end = next,
+                None => break,
+            }
+        }
+
+        let reason = self.input[pos..end].trim();
+        if reason.len() > 0 { Some((end, reason.to_string())) } else { None }
+    }
+
+    fn reason_marker(&self, pos: usize) -> Option<usize> {
+        if let Some(pos) = self.literal(pos, '#') {
+            return Some(pos);
+        }
+
+        ["for", "because"].iter()
+            .filter_map(|word| self.literal_str_case_insensitive(pos, word))
+            .find(|pos| self.char_if(*pos, is_spacing).is_some())
+    }
+
+    fn reason_end(&self, pos: usize) -> bool {
+        if let Some(next) = self.char_if(pos, |c| c == ',' || c == ';') {
+            if self.quietly(|| self.karmachange(self.skip_while(next, is_spacing))).is_some() {
+                return true;
+            }
+        }
+
+        match self.char_if(pos, char::is_whitespace) {
+            Some(next) => self.quietly(|| self.karmachange(next)).is_some(),
+            None => false,
+        }
+    }
+
+    fn number(&self, start: usize) -> Option<(usize, u64)> {
+        let pos = self.skip_while(start, |c| c >= '0' && c <= '9');
+        if pos == start {
+            return None;
+        }
+        match self.input[start..pos].parse() {
+            Ok(n) => Some((pos, n)),
+            Err(_) => {
+                self.fail(ParseErrorKind::NumberTooLarge, start, vec!("smaller number"));
+                None
+            }
+        }
+    }
+
+    fn at_boundary(&self, pos: usize) -> bool {
+        match self.input[pos..].chars().next() {
+            None => true,
+            Some(c) => c.is_whitespace() || ",.;:)".contains(c),
+        }
+    }
+
+    fn any_char(&self, pos: usize) -> Option<(usize, char)> {
+        self.input[pos..].chars().next().map(|c| (pos + c.len_utf8(), c))
+    }
+
+    fn char_if<F>(&self, pos: usize, pred: F) -> Option<usize> where F: Fn(char) -> bool {
+        match self.any_char(pos) {
+            Some((next, c)) if pred(c) => Some(next),
+            _ => None,
+        }
+    }
+
+    fn skip_while<F>(&self, mut pos: usize, pred: F) -> usize where F: Fn(char) -> bool {
+        while let Some(next) = self.char_if(pos, &pred) {
+            pos = next;
+        }
+        pos
+    }
+
+    fn literal(&self, pos: usize, expected: char) -> Option<usize> {
+        self.char_if(pos, |c| c == expected)
+    }
+
+    fn literal_str(&self, pos: usize, expected: &str) -> Option<usize> {
+        if self.input[pos..].starts_with(expected) { Some(pos + expected.len()) } else { None }
+    }
+
+    fn literal_str_case_insensitive(&self, pos: usize, expected: &str) -> Option<usize> {
+        let rest = &self.input[pos..];
+        if rest.len() >= expected.len() && rest.is_char_boundary(expected.len())
+            && rest[..expected.len()].to_ascii_lowercase() == expected {
+            Some(pos + expected.len())
+        } else {
+            None
+        }
+    }
+}
+
+fn is_spacing(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+fn is_ascii_letter(c: char) -> bool {
+    (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z')
+}
+
+fn escape_default(s: &str) -> String {
+    s.chars().flat_map(|c| c.escape_default()).collect()
+}
+
+fn pos_to_line(input: &str, pos: usize) -> (usize, usize) {
+    let mut remaining = pos;
+    let mut lineno = 1;
+    for line in input.lines() {
+        let line_length = line.len() + 1;
+        if remaining < line_length {
+            return (lineno, remaining + 1);
+        }
+        remaining -= line_length;
+        lineno += 1;
+    }
+    (lineno, remaining + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(input: &str) -> Vec<String> {
+        line(input).changes.into_iter().map(|c| c.term).collect()
+    }
+
+    fn error_kinds(input: &str) -> Vec<ParseErrorKind> {
+        line(input).errors.iter().map(|e| e.parse_error().unwrap().kind).collect()
+    }
+
+    fn reasons(input: &str) -> Vec<(String, Option<String>)> {
+        line(input).changes.into_iter().map(|c| (c.term, c.reason)).collect()
+    }
+
+    fn change(input: &str) -> (String, KarmaChange, KarmaStyle) {
+        let changes = line(input).changes;
+        assert_eq!(changes.len(), 1, "one change in {:?}", input);
+        (changes[0].term.clone(), changes[0].change, changes[0].style)
+    }
+
+    #[test]
+    fn implicit_changes() {
+        assert_eq!(change("foo++"), ("foo".to_string(), KarmaChange::new(1, 0), KarmaStyle::Implicit));
+        assert_eq!(change("foo--"), ("foo".to_string(), KarmaChange::new(0, 1), KarmaStyle::Implicit));
+        assert_eq!(change("I like c++"), ("c".to_string(), KarmaChange::new(1, 0), KarmaStyle::Implicit));
+        assert_eq!(terms("foo_bar++ 42--"), vec!("foo_bar", "42"));
+    }
+
+    #[test]
+    fn explicit_changes() {
+        assert_eq!(change("[foo bar]++"), ("foo bar".to_string(), KarmaChange::new(1, 0), KarmaStyle::Notify));
+        assert_eq!(change("(foo bar)--"), ("foo bar".to_string(), KarmaChange::new(0, 1), KarmaStyle::Silent));
+        assert_eq!(terms("[a (b)]++ (a [b])++"), vec!("a (b)", "a [b]"));
+    }
+
+    #[test]
+    fn reasons_end_at_the_next_change() {
+        let reason = |term: &str, reason: Option<&str>| (term.to_string(), reason.map(|r| r.to_string()));
+        assert_eq!(reasons("foo++ for fixing the build"), vec!(reason("foo", Some("fixing the build"))));
+        assert_eq!(reasons("foo++ # great bar-- because no; baz++"),
+                   vec!(reason("foo", Some("great")), reason("bar", Some("no")), reason("baz", None)));
+        assert_eq!(reasons("foo++ for bar++"), vec!(reason("foo", None), reason("bar", None)));
+        assert_eq!(reasons("foo++ # [bar]-- for reasons"), vec!(reason("foo", None), reason("bar", Some("reasons"))));
+    }
+
+    #[test]
+    fn changes_need_a_boundary_after_them() {
+        for end in [" ", ",", ".", ";", ":", ")", "\t", "\u{a0}", "\u{3000}", ""].iter() {
+            assert_eq!(terms(&format!("foo++{}", end)[..]), vec!("foo"));
+        }
+        assert_eq!(terms("foo++bar"), Vec::<String>::new());
+        assert_eq!(terms("foo++!"), Vec::<String>::new());
+        assert_eq!(terms("foo++, bar-- and [baz]++."), vec!("foo", "bar", "baz"));
+    }
+
+    #[test]
+    fn v_and_f_are_not_whitespace() {
+        assert_eq!(terms("a--foo"), Vec::<String>::new());
+        assert_eq!(terms("a++v"), Vec::<String>::new());
+        assert_eq!(terms("a++\u{b}"), vec!("a"));
+        assert_eq!(terms("a++\u{c}"), vec!("a"));
+    }
+
+    #[test]
+    fn hyphenated_terms_are_kept_whole() {
+        assert_eq!(terms("a-b++"), vec!("a-b"));
+        assert_eq!(terms("foo-bar-baz--"), vec!("foo-bar-baz"));
+        assert_eq!(terms("-a++"), vec!("a"));
+    }
+
+    #[test]
+    fn nothing_to_vote_on() {
+        for input in ["", "++", "--", "[]", "()", "foo", "foo + +", "++ foo"].iter() {
+            assert_eq!(terms(input), Vec::<String>::new());
+        }
+    }
+
+    #[test]
+    fn non_ascii_terms() {
+        assert_eq!(terms("caf\u{e9}++"), vec!("caf\u{e9}"));
+        assert_eq!(terms("\u{f1}and\u{fa}++"), vec!("\u{f1}and\u{fa}"));
+        assert_eq!(terms("\u{65e5}\u{672c}++"), vec!("\u{65e5}\u{672c}"));
+        assert_eq!(terms("\u{41c}\u{43e}\u{441}\u{43a}\u{432}\u{430}-- \u{3b1}\u{3b2}\u{3b3}++"), vec!("\u{41c}\u{43e}\u{441}\u{43a}\u{432}\u{430}", "\u{3b1}\u{3b2}\u{3b3}"));
+        assert_eq!(terms("++caf\u{e9}"), vec!("caf\u{e9}"));
+    }
+
+    #[test]
+    fn combining_marks_stay_with_their_term() {
+        assert_eq!(terms("cafe\u{301}++"), vec!("cafe\u{301}"));
+        assert_eq!(terms("a\u{308}rger--"), vec!("a\u{308}rger"));
+        // a mark on its own doesn't start a term
+        assert_eq!(terms("\u{301}++"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn emoji_sequences_stay_whole() {
+        let coder = "\u{1f469}\u{200d}\u{1f4bb}";
+        assert_eq!(terms(&format!("{}++", coder)[..]), vec!(coder));
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+        assert_eq!(terms(&format!("{}--", family)[..]), vec!(family));
+        assert_eq!(terms("\u{1f44d}\u{1f3fd}++"), vec!("\u{1f44d}\u{1f3fd}"));
+        assert_eq!(terms("\u{2764}\u{fe0f}++"), vec!("\u{2764}\u{fe0f}"));
+    }
+
+    #[test]
+    fn urls_are_skipped() {
+        assert_eq!(terms("https://example.com/c++-faq"), Vec::<String>::new());
+        assert_eq!(terms("see http://example.com/a++ b++"), vec!("b"));
+        assert_eq!(terms("git+ssh://host/c++ www.example.com/c++ WWW.example.com/d--"), Vec::<String>::new());
+        // a bare domain isn't a URL
+        assert_eq!(terms("example.com c++"), vec!("c"));
+    }
+
+    #[test]
+    fn code_spans_are_skipped() {
+        assert_eq!(terms("`i++`"), Vec::<String>::new());
+        assert_eq!(terms("use `i--` and `j++ k++` but foo++"), vec!("foo"));
+        // an unclosed backtick is just a character
+        assert_eq!(terms("`i++"), vec!("i"));
+    }
+
+    /// Every combination of a few characters that matter to the grammar, up to
+    /// `length` characters long.
+    fn all_inputs(length: usize) -> Vec<String> {
+        let chars = ['a', '\u{e9}', '-', '+', '[', ']', '(', ')', ' ', '`'];
+        let mut inputs = vec!(String::new());
+        let mut last = vec!(String::new());
+        for _ in 0..length {
+            last = last.iter().flat_map(|s| chars.iter().map(move |c| format!("{}{}", s, c))).collect();
+            inputs.extend(last.iter().cloned());
+        }
+        inputs
+    }
+
+    #[test]
+    fn changes_are_taken_from_the_input() {
+        for input in all_inputs(5) {
+            let parsed = line(&input[..]);
+            for change in parsed.changes.iter() {
+                assert!(change.term.trim().len() > 0, "empty term in {:?}", input);
+                assert!(input.contains(&change.term[..]), "{:?} isn't in {:?}", change.term, input);
+                assert_eq!(change.change.up + change.change.down, 1);
+            }
+            for error in parsed.errors.iter() {
+                assert!(error.parse_error().unwrap().offset <= input.len());
+            }
+        }
+    }
+
+    #[test]
+    fn every_word_can_be_voted_on() {
+        for term in all_inputs(4).into_iter().filter(|t| t.len() > 0 && t.chars().all(|c| c == 'a' || c == '\u{e9}')) {
+            assert_eq!(terms(&format!("{}++", term)[..]), vec!(term.clone()));
+            assert_eq!(terms(&format!("[{} -]-- x", term)[..]), vec!(format!("{} -", term)));
+        }
+    }
+
+    #[test]
+    fn empty_terms_are_reported() {
+        let parsed = line("[]++");
+        assert_eq!(parsed.changes.len(), 0);
+        let error = parsed.errors[0].parse_error().unwrap();
+        assert_eq!(error.kind, ParseErrorKind::EmptyTerm);
+        assert_eq!((error.offset, error.line, error.column), (1, 1, 2));
+        assert_eq!(error.expected, vec!("term"));
+        assert_eq!(error_kinds("( )--"), vec!(ParseErrorKind::EmptyTerm));
+    }
+
+    #[test]
+    fn large_numbers_are_reported() {
+        let parsed = line("x += 99999999999999999999");
+        assert_eq!(parsed.errors.len(), 1);
+        let error = parsed.errors[0].parse_error().unwrap();
+        assert_eq!(error.kind, ParseErrorKind::NumberTooLarge);
+        assert_eq!(error.offset, 5);
+        assert_eq!(parsed.errors[0].to_string(), "number too large at 1:6, expected `smaller number`");
+    }
+
+    #[test]
+    fn errors_dont_drop_other_changes() {
+        assert_eq!(terms("[]++ foo++"), vec!("foo"));
+        assert_eq!(error_kinds("[]++ foo++"), vec!(ParseErrorKind::EmptyTerm));
+        assert_eq!(terms("x += 99999999999999999999 foo++"), vec!("foo"));
+        assert_eq!(error_kinds("x += 99999999999999999999 foo++"), vec!(ParseErrorKind::NumberTooLarge));
+    }
+
+    #[test]
+    fn errors_are_reported_once() {
+        assert_eq!(error_kinds("foo++ for a [] ++ bar++"), vec!());
+        assert_eq!(error_kinds("[]++ ()--"), vec!(ParseErrorKind::EmptyTerm, ParseErrorKind::EmptyTerm));
+    }
+
+    #[test]
+    fn text_in_reasons_is_not_reported() {
+        assert_eq!(error_kinds("foo++ because reasons []++ bar--"), vec!());
+        assert_eq!(error_kinds("foo++ # ()-- and x += 99999999999999999999"), vec!());
+        assert_eq!(terms("foo++ for [] ++ stuff, bar--"), vec!("foo", "bar"));
+    }
+
+    #[test]
+    fn errors_point_at_the_right_line() {
+        let parsed = line("foo\n[]++");
+        let error = parsed.errors[0].parse_error().unwrap();
+        assert_eq!((error.line, error.column), (2, 2));
+    }
+}
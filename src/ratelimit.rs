@@ -0,0 +1,93 @@
+use chrono::{DateTime, Duration, Local};
+use config::Config;
+use term::normalize;
+use std::ascii::AsciiExt;
+use std::cmp::min;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimit {
+    /// This many votes should be counted, which may be fewer than asked for.
+    Allowed(usize),
+    /// The vote should be dropped, `warn` is set the first time a voter hits the limit.
+    Limited { warn: bool }
+}
+
+/// Keeps track of recent votes to stop people from flooding karma.
+///
+/// Votes are allowed as long as the voter has given no more than `term_burst`
+/// votes on the same term within the last `term_window`, and no more than
+/// `voter_burst` votes in total within the last `voter_window`. A change like
+/// `term += 3` is three votes.
+pub struct RateLimiter {
+    term_window: Duration,
+    term_burst: usize,
+    voter_window: Duration,
+    voter_burst: usize,
+    term_votes: HashMap<(String, String, String), Vec<DateTime<Local>>>,
+    voter_votes: HashMap<(String, String), Vec<DateTime<Local>>>,
+    warned: HashSet<(String, String)>
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config) -> RateLimiter {
+        RateLimiter {
+            term_window: Duration::seconds(config.term_cooldown as i64),
+            term_burst: config.term_burst,
+            voter_window: Duration::seconds(config.voter_cooldown as i64),
+            voter_burst: config.voter_burst,
+            term_votes: HashMap::new(),
+            voter_votes: HashMap::new(),
+            warned: HashSet::new()
+        }
+    }
+
+    /// Check how many of the `votes` given on a term at once are allowed, and
+    /// count those towards the limits.
+    pub fn check(&mut self, network: &str, voter: &str, term: &str, votes: usize) -> RateLimit {
+        let now = Local::now();
+        let voter_key = (network.to_string(), voter.to_ascii_lowercase());
+        let term_key = (network.to_string(), voter.to_ascii_lowercase(), normalize(term));
+
+        let voter_count = prune(self.voter_votes.entry(voter_key.clone()).or_insert(Vec::new()), now, self.voter_window);
+        let term_count = prune(self.term_votes.entry(term_key.clone()).or_insert(Vec::new()), now, self.term_window);
+
+        let mut allowed = votes;
+        if self.voter_burst > 0 {
+            allowed = min(allowed, self.voter_burst.saturating_sub(voter_count));
+        }
+        if self.term_burst > 0 {
+            allowed = min(allowed, self.term_burst.saturating_sub(term_count));
+        }
+        if allowed == 0 {
+            let warn = self.warned.insert(voter_key);
+            return RateLimit::Limited { warn: warn };
+        }
+
+        if voter_count == 0 {
+            self.warned.remove(&voter_key);
+        }
+        for _ in 0..allowed {
+            self.voter_votes.get_mut(&voter_key).unwrap().push(now);
+            self.term_votes.get_mut(&term_key).unwrap().push(now);
+        }
+        self.forget_idle(now);
+        RateLimit::Allowed(allowed)
+    }
+
+    /// Drop bookkeeping for voters that haven't voted recently.
+    fn forget_idle(&mut self, now: DateTime<Local>) {
+        let term_window = self.term_window;
+        let voter_window = self.voter_window;
+        self.term_votes = self.term_votes.drain().filter(|&(_, ref v)| v.iter().any(|t| now - *t < term_window)).collect();
+        self.voter_votes = self.voter_votes.drain().filter(|&(_, ref v)| v.iter().any(|t| now - *t < voter_window)).collect();
+        let voter_votes = &self.voter_votes;
+        self.warned.retain(|k| voter_votes.contains_key(k));
+    }
+}
+
+/// Remove all votes that are outside of the window and return how many are left.
+fn prune(votes: &mut Vec<DateTime<Local>>, now: DateTime<Local>, window: Duration) -> usize {
+    votes.retain(|t| now - *t < window);
+    votes.len()
+}
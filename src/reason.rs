@@ -0,0 +1,128 @@
+use rustc_serialize::json;
+use rustc_serialize::json::ToJson;
+use error::KarmaError;
+use karma::format_time;
+use chrono::{DateTime, Local};
+use dazeus::{DaZeusClient, Scope};
+use store::{KarmaStore, Record};
+use term::normalize;
+
+/// How many reasons are kept for a single term.
+const MAX_REASONS: usize = 50;
+
+/// Why someone voted on a term, as given after the `#`, `for` or `because`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reason {
+    pub voter: String,
+    pub reason: String,
+    pub positive: bool,
+    pub time: DateTime<Local>
+}
+
+impl Reason {
+    pub fn new(voter: &str, reason: &str, positive: bool) -> Reason {
+        Reason {
+            voter: voter.to_string(),
+            reason: reason.to_string(),
+            positive: positive,
+            time: Local::now()
+        }
+    }
+
+    pub fn from_json(data: &json::Json) -> Result<Reason, Box<::std::error::Error>> {
+        macro_rules! get_key {
+            ($o:expr, $s:expr, $i_is:ident, $i_as:ident) => (match $o.get($s) {
+                Some(m) if m.$i_is() => m.$i_as().unwrap(),
+                _ => return Err(From::from(KarmaError::new(&format!("No value or invalid value for key '{}'", $s)[..])))
+            });
+        }
+
+        match data.as_object() {
+            Some(obj) => {
+                let time = get_key!(obj, "time", is_string, as_string);
+
+                Ok(Reason {
+                    voter: get_key!(obj, "voter", is_string, as_string).to_string(),
+                    reason: get_key!(obj, "reason", is_string, as_string).to_string(),
+                    positive: get_key!(obj, "positive", is_boolean, as_boolean),
+                    time: try!(time.parse::<DateTime<Local>>())
+                })
+            },
+            None => Err(From::from(KarmaError::new("Invalid json: reason is not an object")))
+        }
+    }
+}
+
+impl json::ToJson for Reason {
+    fn to_json(&self) -> json::Json {
+        let mut obj = json::Object::new();
+        obj.insert("voter".to_string(), self.voter.to_json());
+        obj.insert("reason".to_string(), self.reason.to_json());
+        obj.insert("positive".to_string(), self.positive.to_json());
+        obj.insert("time".to_string(), format_time(&self.time).to_json());
+        obj.to_json()
+    }
+}
+
+/// The most recent reasons given for votes on a term, oldest first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reasons {
+    pub term: String,
+    pub reasons: Vec<Reason>
+}
+
+impl Reasons {
+    pub fn new(term: &str) -> Reasons {
+        Reasons { term: normalize(term), reasons: Vec::new() }
+    }
+
+    pub fn from_str(term: &str, s: &str) -> Result<Reasons, Box<::std::error::Error>> {
+        let data = try!(json::Json::from_str(s));
+        let list = match data.as_array() {
+            Some(list) => list,
+            None => return Err(From::from(KarmaError::new("Invalid json: reasons are not an array")))
+        };
+
+        let mut reasons = Reasons::new(term);
+        for item in list {
+            reasons.reasons.push(try!(Reason::from_json(item)));
+        }
+        Ok(reasons)
+    }
+
+    /// Retrieve the reasons given for a term, an unknown term has none.
+    pub fn load(store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope, term: &str) -> Result<Reasons, Box<::std::error::Error>> {
+        match try!(store.get_record(dazeus, scope, Record::Reasons, &normalize(term)[..])) {
+            Some(s) => Reasons::from_str(term, &s[..]),
+            None => Ok(Reasons::new(term)),
+        }
+    }
+
+    pub fn save(&self, store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope) -> Result<(), Box<::std::error::Error>> {
+        store.put_record(dazeus, scope, Record::Reasons, &self.term[..], Some(&self.reasons.to_json().to_string()[..]))
+    }
+
+    /// Remember a reason, forgetting the oldest ones if there are too many.
+    pub fn add(&mut self, reason: Reason) {
+        self.reasons.push(reason);
+        self.truncate();
+    }
+
+    pub fn merge(&mut self, other: &Reasons) {
+        self.reasons.extend(other.reasons.iter().cloned());
+        self.reasons.sort_by(|a, b| a.time.cmp(&b.time));
+        self.truncate();
+    }
+
+    /// The most recent reason for a positive or negative vote.
+    pub fn latest(&self, positive: bool) -> Option<&Reason> {
+        self.reasons.iter().rev().find(|r| r.positive == positive)
+    }
+
+    fn truncate(&mut self) {
+        if self.reasons.len() > MAX_REASONS {
+            let excess = self.reasons.len() - MAX_REASONS;
+            self.reasons.drain(..excess);
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use chrono::{DateTime, Local};
+use dazeus::{DaZeusClient, Scope};
+use error::KarmaError;
+use history::{Vote, VoteHistory, MAX_VOTES};
+use karma::{format_time, KarmaChange, KarmaStyle, KarmaValue};
+use store::{scope_key, KarmaStore, Record, RepairReport};
+use term::normalize;
+use rusqlite::{Connection, ToSql};
+use std::cmp::max;
+use std::error::Error;
+use std::time::Duration;
+
+const SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS karma (
+        scope TEXT NOT NULL,
+        term TEXT NOT NULL,
+        up INTEGER NOT NULL,
+        down INTEGER NOT NULL,
+        first_vote TEXT NOT NULL,
+        last_vote TEXT NOT NULL,
+        PRIMARY KEY (scope, term)
+    );
+    CREATE TABLE IF NOT EXISTS votes (
+        id INTEGER PRIMARY KEY,
+        scope TEXT NOT NULL,
+        term TEXT NOT NULL,
+        voter TEXT NOT NULL,
+        channel TEXT NOT NULL,
+        up INTEGER NOT NULL,
+        down INTEGER NOT NULL,
+        style TEXT NOT NULL,
+        time TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS votes_by_term ON votes (scope, term);
+    CREATE TABLE IF NOT EXISTS records (
+        scope TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (scope, kind, key)
+    );
+";
+
+/// How long to wait for another process that is writing to the database.
+const BUSY_TIMEOUT_MS: u64 = 5000;
+
+/// Keeps karma in an SQLite database, with a row for every term, every vote
+/// and every record kept next to the karma. Votes are added to the stored karma inside a transaction, so plugin
+/// instances sharing a database never lose each other's votes.
+pub struct SqliteStore {
+    conn: Connection
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> Result<SqliteStore, Box<Error>> {
+        let conn = try!(Connection::open(path));
+        try!(conn.busy_timeout(Duration::from_millis(BUSY_TIMEOUT_MS)));
+        try!(conn.execute_batch(SCHEMA));
+        Ok(SqliteStore { conn: conn })
+    }
+
+    /// Run `f` in a transaction, which is rolled back if it fails. The write
+    /// lock is taken right away, so concurrent writers wait for each other
+    /// instead of failing halfway.
+    fn transaction<T, F>(&self, f: F) -> Result<T, Box<Error>> where F: FnOnce() -> Result<T, Box<Error>> {
+        try!(self.conn.execute_batch("BEGIN IMMEDIATE"));
+        match f() {
+            Ok(value) => {
+                try!(self.conn.execute_batch("COMMIT"));
+                Ok(value)
+            },
+            Err(e) => {
+                if let Err(rollback) = self.conn.execute_batch("ROLLBACK") {
+                    warn!("Could not roll back a failed transaction: {}", rollback);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn insert_vote(&self, scope: &str, vote: &Vote) -> Result<(), Box<Error>> {
+        let time = format_time(&vote.time);
+        try!(self.conn.execute("INSERT INTO votes (scope, term, voter, channel, up, down, style, time) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            &[&scope as &ToSql, &vote.term, &vote.voter, &vote.channel, &(vote.change.up as i64), &(vote.change.down as i64), &vote.style.to_str(), &time]));
+        Ok(())
+    }
+
+    /// Remove all but the latest votes on a term.
+    fn forget_old_votes(&self, scope: &str, term: &str) -> Result<(), Box<Error>> {
+        try!(self.conn.execute("DELETE FROM votes WHERE scope = ?1 AND term = ?2 AND id NOT IN \
+                                (SELECT id FROM votes WHERE scope = ?1 AND term = ?2 ORDER BY time DESC, id DESC LIMIT ?3)",
+            &[&scope as &ToSql, &term, &(MAX_VOTES as i64)]));
+        Ok(())
+    }
+}
+
+impl KarmaStore for SqliteStore {
+    fn get(&self, _: &DaZeusClient, scope: Scope, term: &str) -> Result<Option<KarmaValue>, Box<Error>> {
+        let mut stmt = try!(self.conn.prepare("SELECT term, up, down, first_vote, last_vote FROM karma WHERE scope = ?1 AND term = ?2"));
+        let mut rows = try!(stmt.query(&[&scope_key(&scope) as &ToSql, &normalize(term)]));
+        match try!(rows.next()) {
+            Some(row) => Ok(Some(try!(karma_from_row(try!(row.get(0)), try!(row.get(1)), try!(row.get(2)), try!(row.get(3)), try!(row.get(4)))))),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, _: &DaZeusClient, scope: Scope, karma: &KarmaValue) -> Result<(), Box<Error>> {
+        try!(self.conn.execute("INSERT OR REPLACE INTO karma (scope, term, up, down, first_vote, last_vote) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            &[&scope_key(&scope) as &ToSql, &karma.term, &(karma.votes.up as i64), &(karma.votes.down as i64),
+              &format_time(&karma.first_vote), &format_time(&karma.last_vote)]));
+        Ok(())
+    }
+
+    fn apply_vote(&self, dazeus: &DaZeusClient, scope: Scope, vote: &Vote) -> Result<KarmaValue, Box<Error>> {
+        let key = scope_key(&scope);
+        let time = format_time(&vote.time);
+        try!(self.transaction(|| {
+            try!(self.conn.execute("INSERT OR IGNORE INTO karma (scope, term, up, down, first_vote, last_vote) VALUES (?1, ?2, 0, 0, ?3, ?3)",
+                &[&key as &ToSql, &vote.term, &time]));
+            try!(self.conn.execute("UPDATE karma SET up = up + ?3, down = down + ?4, last_vote = ?5 WHERE scope = ?1 AND term = ?2",
+                &[&key as &ToSql, &vote.term, &(vote.change.up as i64), &(vote.change.down as i64), &time]));
+            try!(self.insert_vote(&key[..], vote));
+            self.forget_old_votes(&key[..], &vote.term[..])
+        }));
+
+        match try!(self.get(dazeus, scope, &vote.term[..])) {
+            Some(karma) => Ok(karma),
+            None => Err(From::from(KarmaError::new(&format!("The karma of '{}' disappeared while voting", vote.term)[..]))),
+        }
+    }
+
+    fn list(&self, _: &DaZeusClient, scope: Scope) -> Result<Vec<KarmaValue>, Box<Error>> {
+        let mut stmt = try!(self.conn.prepare("SELECT term, up, down, first_vote, last_vote FROM karma WHERE scope = ?1"));
+        let rows = try!(stmt.query_map(&[&scope_key(&scope) as &ToSql], |row| {
+            Ok((try!(row.get(0)), try!(row.get(1)), try!(row.get(2)), try!(row.get(3)), try!(row.get(4))))
+        }));
+
+        let mut karmas = Vec::new();
+        for row in rows {
+            let (term, up, down, first_vote, last_vote): (String, i64, i64, String, String) = try!(row);
+            match karma_from_row(term.clone(), up, down, first_vote, last_vote) {
+                Ok(karma) => karmas.push(karma),
+                Err(e) => warn!("Could not read karma of '{}' from the database: {}", term, e),
+            }
+        }
+        Ok(karmas)
+    }
+
+    fn delete(&self, _: &DaZeusClient, scope: Scope, term: &str) -> Result<bool, Box<Error>> {
+        let key = scope_key(&scope);
+        let term = normalize(term);
+        self.transaction(|| {
+            let deleted = try!(self.conn.execute("DELETE FROM karma WHERE scope = ?1 AND term = ?2", &[&key as &ToSql, &term]));
+            try!(self.conn.execute("DELETE FROM votes WHERE scope = ?1 AND term = ?2", &[&key as &ToSql, &term]));
+            Ok(deleted > 0)
+        })
+    }
+
+    fn history(&self, _: &DaZeusClient, scope: Scope, term: &str) -> Result<VoteHistory, Box<Error>> {
+        let mut stmt = try!(self.conn.prepare("SELECT voter, channel, up, down, style, time FROM votes WHERE scope = ?1 AND term = ?2 ORDER BY time, id"));
+        let rows = try!(stmt.query_map(&[&scope_key(&scope) as &ToSql, &normalize(term)], |row| {
+            Ok((try!(row.get(0)), try!(row.get(1)), try!(row.get(2)), try!(row.get(3)), try!(row.get(4)), try!(row.get(5))))
+        }));
+
+        let mut history = VoteHistory::new(term);
+        for row in rows {
+            let (voter, channel, up, down, style, time): (String, String, i64, i64, String, String) = try!(row);
+            history.votes.push(Vote {
+                voter: voter,
+                channel: channel,
+                term: history.term.clone(),
+                change: KarmaChange::new(up as u64, down as u64),
+                style: match KarmaStyle::from_str(&style[..]) {
+                    Some(s) => s,
+                    None => return Err(From::from(KarmaError::new(&format!("Unknown karma style '{}'", style)[..]))),
+                },
+                time: try!(time.parse::<DateTime<Local>>()),
+                id: None
+            });
+        }
+        Ok(history)
+    }
+
+    fn put_history(&self, _: &DaZeusClient, scope: Scope, history: &VoteHistory) -> Result<(), Box<Error>> {
+        let key = scope_key(&scope);
+        self.transaction(|| {
+            try!(self.conn.execute("DELETE FROM votes WHERE scope = ?1 AND term = ?2", &[&key as &ToSql, &history.term]));
+            for vote in history.votes.iter() {
+                let mut vote = vote.clone();
+                vote.term = history.term.clone();
+                try!(self.insert_vote(&key[..], &vote));
+            }
+            self.forget_old_votes(&key[..], &history.term[..])
+        })
+    }
+
+    fn repair(&self, _: &DaZeusClient, scope: Scope) -> Result<RepairReport, Box<Error>> {
+        let key = scope_key(&scope);
+        let now = format_time(&Local::now());
+        let is_time = |s: &str| s.parse::<DateTime<Local>>().is_ok();
+        let mut report = RepairReport { checked: 0, repaired: 0 };
+
+        let karmas: Vec<(String, i64, i64, String, String)> = {
+            let mut stmt = try!(self.conn.prepare("SELECT term, up, down, first_vote, last_vote FROM karma WHERE scope = ?1"));
+            let rows = try!(stmt.query_map(&[&key as &ToSql], |row| {
+                Ok((try!(row.get(0)), try!(row.get(1)), try!(row.get(2)), try!(row.get(3)), try!(row.get(4))))
+            }));
+            try!(rows.collect())
+        };
+        for (term, up, down, first_vote, last_vote) in karmas {
+            report.checked += 1;
+            if up >= 0 && down >= 0 && is_time(&first_vote[..]) && is_time(&last_vote[..]) {
+                continue;
+            }
+
+            warn!("The stored karma of '{}' is corrupt, repairing it", term);
+            let first_vote = if is_time(&first_vote[..]) { first_vote } else { now.clone() };
+            let last_vote = if is_time(&last_vote[..]) { last_vote } else { now.clone() };
+            try!(self.conn.execute("UPDATE karma SET up = ?3, down = ?4, first_vote = ?5, last_vote = ?6 WHERE scope = ?1 AND term = ?2",
+                &[&key as &ToSql, &term, &max(up, 0), &max(down, 0), &first_vote, &last_vote]));
+            report.repaired += 1;
+        }
+
+        // votes that can't be read are of no use to anyone
+        let votes: Vec<(i64, String, i64, i64, String, String)> = {
+            let mut stmt = try!(self.conn.prepare("SELECT id, term, up, down, style, time FROM votes WHERE scope = ?1"));
+            let rows = try!(stmt.query_map(&[&key as &ToSql], |row| {
+                Ok((try!(row.get(0)), try!(row.get(1)), try!(row.get(2)), try!(row.get(3)), try!(row.get(4)), try!(row.get(5))))
+            }));
+            try!(rows.collect())
+        };
+        for (id, term, up, down, style, time) in votes {
+            if up >= 0 && down >= 0 && KarmaStyle::from_str(&style[..]).is_some() && is_time(&time[..]) {
+                continue;
+            }
+
+            warn!("Removing a corrupt vote on '{}' from the database", term);
+            try!(self.conn.execute("DELETE FROM votes WHERE id = ?1", &[&id as &ToSql]));
+            report.repaired += 1;
+        }
+        Ok(report)
+    }
+
+    fn get_record(&self, _: &DaZeusClient, scope: Scope, record: Record, key: &str) -> Result<Option<String>, Box<Error>> {
+        let mut stmt = try!(self.conn.prepare("SELECT value FROM records WHERE scope = ?1 AND kind = ?2 AND key = ?3"));
+        let mut rows = try!(stmt.query(&[&scope_key(&scope) as &ToSql, &record.to_str(), &key]));
+        match try!(rows.next()) {
+            Some(row) => Ok(Some(try!(row.get(0)))),
+            None => Ok(None),
+        }
+    }
+
+    fn put_record(&self, _: &DaZeusClient, scope: Scope, record: Record, key: &str, value: Option<&str>) -> Result<(), Box<Error>> {
+        let scope = scope_key(&scope);
+        match value {
+            Some(value) => try!(self.conn.execute("INSERT OR REPLACE INTO records (scope, kind, key, value) VALUES (?1, ?2, ?3, ?4)",
+                &[&scope as &ToSql, &record.to_str(), &key, &value])),
+            None => try!(self.conn.execute("DELETE FROM records WHERE scope = ?1 AND kind = ?2 AND key = ?3",
+                &[&scope as &ToSql, &record.to_str(), &key])),
+        };
+        Ok(())
+    }
+}
+
+fn karma_from_row(term: String, up: i64, down: i64, first_vote: String, last_vote: String) -> Result<KarmaValue, Box<Error>> {
+    let mut karma = KarmaValue::new(&term[..]);
+    karma.votes = KarmaChange::new(up as u64, down as u64);
+    karma.first_vote = try!(first_vote.parse::<DateTime<Local>>());
+    karma.last_vote = try!(last_vote.parse::<DateTime<Local>>());
+    Ok(karma)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alias;
+    use chrono::Local;
+    use config::Config;
+    use dazeus::Scope;
+    use fake::{FakeDaZeus, CHANNEL, NETWORK};
+    use history::Vote;
+    use karma::{Karma, KarmaChange, KarmaOperator, KarmaStyle};
+    use store::KarmaStore;
+    use trend::Trend;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::thread;
+
+    const WRITERS: usize = 8;
+    const VOTES: usize = 50;
+
+    #[test]
+    fn concurrent_votes_are_never_lost() {
+        let path = env::temp_dir().join(format!("karma-stress-{}.sqlite", process::id()));
+        let path = path.to_str().unwrap().to_string();
+        SqliteStore::open(&path[..]).unwrap();
+
+        // every writer is a plugin instance of its own, sharing the database
+        let writers: Vec<_> = (0..WRITERS).map(|i| {
+            let path = path.clone();
+            thread::spawn(move || {
+                let dazeus = FakeDaZeus::new();
+                let store = SqliteStore::open(&path[..]).unwrap();
+                let vote = Vote::new(&format!("voter{}", i)[..], CHANNEL, &Karma {
+                    term: "foo".to_string(),
+                    change: KarmaChange::new(1, 0),
+                    operator: KarmaOperator::Postfix,
+                    style: KarmaStyle::Notify,
+                    reason: None
+                });
+                for _ in 0..VOTES {
+                    store.apply_vote(&dazeus, Scope::network(NETWORK), &vote).unwrap();
+                }
+            })
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let dazeus = FakeDaZeus::new();
+        let store = SqliteStore::open(&path[..]).unwrap();
+        let karma = store.get(&dazeus, Scope::network(NETWORK), "foo").unwrap().unwrap();
+        let history = store.history(&dazeus, Scope::network(NETWORK), "foo").unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(karma.votes.up as usize, WRITERS * VOTES);
+        assert_eq!(history.votes.len(), WRITERS * VOTES);
+    }
+
+    #[test]
+    fn aliases_and_trends_are_kept_in_the_database() {
+        let path = env::temp_dir().join(format!("karma-records-{}.sqlite", process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let dazeus = FakeDaZeus::new();
+        let store = SqliteStore::open(&path[..]).unwrap();
+        let scope = Scope::network(NETWORK);
+
+        alias::set_alias(&store, &dazeus, scope.clone(), "foo", "bar").unwrap();
+        let mut trend = Trend::new("bar");
+        trend.days.insert(Local::today().naive_local(), KarmaChange::new(2, 1));
+        trend.save(&store, &dazeus, scope.clone()).unwrap();
+
+        let reopened = SqliteStore::open(&path[..]).unwrap();
+        let resolved = alias::resolve(&reopened, &dazeus, scope.clone(), "foo");
+        let loaded = Trend::load(&reopened, &dazeus, scope.clone(), "bar").unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(resolved, "bar");
+        assert_eq!(loaded, trend);
+        let config = Config::new();
+        assert_eq!(dazeus.property(&format!("{}foo", config.alias_prefix())[..], scope.clone()), None);
+        assert_eq!(dazeus.property(&format!("{}bar", config.trend_prefix())[..], scope), None);
+    }
+}
@@ -0,0 +1,670 @@
+use rustc_serialize::json;
+use rustc_serialize::json::ToJson;
+use chrono::Local;
+use dazeus::{DaZeusClient, Scope};
+use config::Config;
+use history::{Vote, VoteHistory};
+use karma::{self, KarmaValue};
+use term::normalize;
+use std::cell::{Cell, RefCell};
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::atomic::{self, AtomicUsize, ATOMIC_USIZE_INIT};
+
+/// Where karma and the votes that led to it are kept.
+///
+/// Every method gets the DaZeus client of the event that is being handled, so
+/// stores that keep their data in DaZeus can use it. Terms are always
+/// normalized and aliases are resolved before they get here.
+pub trait KarmaStore {
+    /// The karma of a term, `None` if nothing is stored for it.
+    fn get(&self, dazeus: &DaZeusClient, scope: Scope, term: &str) -> Result<Option<KarmaValue>, Box<Error>>;
+
+    /// Store the karma of a term, replacing whatever was stored before.
+    fn put(&self, dazeus: &DaZeusClient, scope: Scope, karma: &KarmaValue) -> Result<(), Box<Error>>;
+
+    /// Count a vote towards the karma of `vote.term` and add it to the history
+    /// of the term. Returns the updated karma.
+    fn apply_vote(&self, dazeus: &DaZeusClient, scope: Scope, vote: &Vote) -> Result<KarmaValue, Box<Error>>;
+
+    /// The karma of all terms in a scope.
+    fn list(&self, dazeus: &DaZeusClient, scope: Scope) -> Result<Vec<KarmaValue>, Box<Error>>;
+
+    /// Remove the karma and history of a term, returns whether it had any karma.
+    fn delete(&self, dazeus: &DaZeusClient, scope: Scope, term: &str) -> Result<bool, Box<Error>>;
+
+    /// All votes on a term, oldest first.
+    fn history(&self, dazeus: &DaZeusClient, scope: Scope, term: &str) -> Result<VoteHistory, Box<Error>>;
+
+    /// Replace the vote history of a term.
+    fn put_history(&self, dazeus: &DaZeusClient, scope: Scope, history: &VoteHistory) -> Result<(), Box<Error>>;
+
+    /// Go over everything stored in a scope and repair what can't be read.
+    fn repair(&self, dazeus: &DaZeusClient, scope: Scope) -> Result<RepairReport, Box<Error>>;
+
+    /// A record kept next to the karma, as it was stored.
+    fn get_record(&self, dazeus: &DaZeusClient, scope: Scope, record: Record, key: &str) -> Result<Option<String>, Box<Error>>;
+
+    /// Store a record kept next to the karma, or remove it if `value` is `None`.
+    fn put_record(&self, dazeus: &DaZeusClient, scope: Scope, record: Record, key: &str, value: Option<&str>) -> Result<(), Box<Error>>;
+}
+
+/// The kinds of data kept next to the karma, which stores keep without having
+/// to understand them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Record {
+    /// The number of votes per day on a term.
+    Trend,
+    /// The latest reasons given for votes on a term.
+    Reasons,
+    /// The term votes on an alias are counted towards.
+    Alias,
+    /// The patterns added to a deny or allow list, by the name of the list.
+    Filter
+}
+
+impl Record {
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            Record::Trend => "trend",
+            Record::Reasons => "reasons",
+            Record::Alias => "alias",
+            Record::Filter => "filter",
+        }
+    }
+}
+
+/// What `KarmaStore::repair` found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RepairReport {
+    /// The number of terms that were looked at.
+    pub checked: usize,
+    /// The number of records that had to be repaired.
+    pub repaired: usize
+}
+
+/// Tells apart the stores created by this process, even within the same nanosecond.
+static STORES_CREATED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Keeps karma in DaZeus properties as JSON, one property for the karma and
+/// one for the vote history of every term.
+///
+/// DaZeus can't compare-and-swap properties, so plugin instances never vote by
+/// rewriting a property that another instance may be rewriting as well. Every
+/// instance counts its votes in a tally of its own and adds them to a history
+/// of its own, and reading a term adds all of those up. Storing the karma of a
+/// term outright, like setting or resetting it does, bumps its revision, and
+/// tallies that were counted on top of an older revision no longer count.
+///
+/// Records that can't be read are moved to a quarantine property and replaced
+/// by whatever could be salvaged from them as soon as they are read.
+pub struct DaZeusStore {
+    store_prefix: String,
+    history_prefix: String,
+    tally_prefix: String,
+    votes_prefix: String,
+    quarantine_prefix: String,
+    trend_prefix: String,
+    reasons_prefix: String,
+    alias_prefix: String,
+    filter_prefix: String,
+    channels_property: String,
+    /// The channels we know are listed in the channels property of their network.
+    channels_listed: RefCell<HashSet<(String, String)>>,
+    /// Names the tallies and histories only this plugin instance writes.
+    writer: String,
+    /// The number of votes we added to a history, to give each an id.
+    votes_written: Cell<u64>
+}
+
+impl DaZeusStore {
+    pub fn new(config: &Config) -> DaZeusStore {
+        DaZeusStore {
+            store_prefix: config.store_prefix.clone(),
+            history_prefix: config.history_prefix(),
+            tally_prefix: config.tally_prefix(),
+            votes_prefix: config.votes_prefix(),
+            quarantine_prefix: config.quarantine_prefix(),
+            trend_prefix: config.trend_prefix(),
+            reasons_prefix: config.reasons_prefix(),
+            alias_prefix: config.alias_prefix(),
+            filter_prefix: config.filter_prefix(),
+            channels_property: config.channels_property(),
+            channels_listed: RefCell::new(HashSet::new()),
+            writer: format!("{}-{}", Local::now().format("%s%f"), STORES_CREATED.fetch_add(1, atomic::Ordering::SeqCst)),
+            votes_written: Cell::new(0)
+        }
+    }
+
+    fn karma_property(&self, term: &str) -> String {
+        format!("{}{}", self.store_prefix, normalize(term))
+    }
+
+    fn history_property(&self, term: &str) -> String {
+        format!("{}{}", self.history_prefix, normalize(term))
+    }
+
+    fn tally_property(&self, term: &str, writer: &str) -> String {
+        format!("{}{}.{}", self.tally_prefix, normalize(term), writer)
+    }
+
+    fn votes_property(&self, term: &str, writer: &str) -> String {
+        format!("{}{}.{}", self.votes_prefix, normalize(term), writer)
+    }
+
+    fn record_property(&self, record: Record, key: &str) -> String {
+        let prefix = match record {
+            Record::Trend => &self.trend_prefix,
+            Record::Reasons => &self.reasons_prefix,
+            Record::Alias => &self.alias_prefix,
+            Record::Filter => &self.filter_prefix,
+        };
+        format!("{}{}", prefix, key)
+    }
+
+    /// The writers that have a property of their own for a term under `prefix`.
+    fn writers(&self, dazeus: &DaZeusClient, scope: Scope, prefix: &str, term: &str) -> Vec<String> {
+        let prefix = format!("{}{}.", prefix, normalize(term));
+        dazeus.get_property_keys(&prefix[..], scope).into_iter()
+            .map(|key| if key.starts_with(&prefix[..]) { key[prefix.len()..].to_string() } else { key })
+            // writers never have a dot in their name, terms starting with this one might
+            .filter(|writer| !writer.contains('.'))
+            .collect()
+    }
+
+    /// Move a record we can't read out of the way, so it can be looked at later.
+    fn quarantine(&self, dazeus: &DaZeusClient, scope: Scope, kind: &str, term: &str, raw: &str, error: &Error) {
+        let property = format!("{}{}.{}.{}", self.quarantine_prefix, kind, normalize(term), Local::now().format("%Y%m%dT%H%M%S"));
+        warn!("The stored {} of '{}' is corrupt ({}), moved it to '{}'", kind, term, error, property);
+        dazeus.set_property(&property[..], raw, scope);
+    }
+
+    /// Read the karma or tally of a term kept in `property`, repairing it if it
+    /// is corrupt. Also returns whether it had to be repaired.
+    fn read_karma(&self, dazeus: &DaZeusClient, scope: Scope, property: &str, kind: &str, term: &str) -> Result<(Option<KarmaValue>, bool), Box<Error>> {
+        let response = dazeus.get_property(property, scope.clone());
+        let raw = match response.get_str("value") {
+            Some(s) => s,
+            None => return Ok((None, false)),
+        };
+
+        match KarmaValue::from_str(raw) {
+            Ok(karma) => Ok((Some(karma), false)),
+            Err(e) if karma::is_newer_schema(raw) => Err(e),
+            Err(e) => {
+                self.quarantine(dazeus, scope.clone(), kind, term, raw, &*e);
+                let karma = KarmaValue::salvage(term, raw);
+                self.write_karma(dazeus, scope, property, &karma);
+                Ok((Some(karma), true))
+            }
+        }
+    }
+
+    /// Read the stored karma of a term with the tallies that still count added
+    /// to it, repairing whatever is corrupt. Also returns whether anything had
+    /// to be repaired.
+    fn read_term(&self, dazeus: &DaZeusClient, scope: Scope, term: &str) -> Result<(Option<KarmaValue>, bool), Box<Error>> {
+        let (mut karma, mut repaired) = try!(self.read_karma(dazeus, scope.clone(), &self.karma_property(term)[..], "karma", term));
+        let revision = karma.as_ref().map(|k| k.revision).unwrap_or(0);
+
+        for writer in self.writers(dazeus, scope.clone(), &self.tally_prefix[..], term) {
+            let property = self.tally_property(term, &writer[..]);
+            let (tally, tally_repaired) = try!(self.read_karma(dazeus, scope.clone(), &property[..], "tally", term));
+            repaired = repaired || tally_repaired;
+            if let Some(tally) = tally {
+                if tally.revision != revision {
+                    continue;
+                }
+                karma = Some(match karma {
+                    Some(mut karma) => {
+                        karma.merge(&tally);
+                        karma
+                    },
+                    None => tally,
+                });
+            }
+        }
+        Ok((karma, repaired))
+    }
+
+    /// Read the votes of a term kept in `property`, repairing them if they are
+    /// corrupt. Also returns whether they had to be repaired.
+    fn read_history(&self, dazeus: &DaZeusClient, scope: Scope, property: &str, kind: &str, term: &str) -> Result<(VoteHistory, bool), Box<Error>> {
+        let response = dazeus.get_property(property, scope.clone());
+        let raw = match response.get_str("value") {
+            Some(s) => s,
+            None => return Ok((VoteHistory::new(term), false)),
+        };
+
+        match VoteHistory::from_str(term, raw) {
+            Ok(history) => Ok((history, false)),
+            Err(e) => {
+                self.quarantine(dazeus, scope.clone(), kind, term, raw, &*e);
+                let history = VoteHistory::salvage(term, raw);
+                self.write_history(dazeus, scope, property, &history);
+                Ok((history, true))
+            }
+        }
+    }
+
+    /// Read the stored history of a term with the votes of every writer added
+    /// to it, repairing whatever is corrupt. Also returns whether anything had
+    /// to be repaired.
+    fn read_term_history(&self, dazeus: &DaZeusClient, scope: Scope, term: &str) -> Result<(VoteHistory, bool), Box<Error>> {
+        let (mut history, mut repaired) = try!(self.read_history(dazeus, scope.clone(), &self.history_property(term)[..], "history", term));
+        for writer in self.writers(dazeus, scope.clone(), &self.votes_prefix[..], term) {
+            let property = self.votes_property(term, &writer[..]);
+            let (votes, votes_repaired) = try!(self.read_history(dazeus, scope.clone(), &property[..], "votes", term));
+            repaired = repaired || votes_repaired;
+            history.merge(&votes);
+        }
+        Ok((history, repaired))
+    }
+
+    /// The terms that have karma stored in a scope, or a tally counted for them.
+    fn stored_terms(&self, dazeus: &DaZeusClient, scope: Scope) -> Vec<String> {
+        let prefix = &self.store_prefix[..];
+        let mut terms: Vec<String> = dazeus.get_property_keys(prefix, scope.clone()).into_iter().map(|key| {
+            if key.starts_with(prefix) { key[prefix.len()..].to_string() } else { key }
+        }).collect();
+
+        let prefix = &self.tally_prefix[..];
+        for key in dazeus.get_property_keys(prefix, scope) {
+            let key = if key.starts_with(prefix) { &key[prefix.len()..] } else { &key[..] };
+            if let Some(dot) = key.rfind('.') {
+                terms.push(key[..dot].to_string());
+            }
+        }
+        terms.sort();
+        terms.dedup();
+        terms
+    }
+
+    fn write_karma(&self, dazeus: &DaZeusClient, scope: Scope, property: &str, karma: &KarmaValue) {
+        dazeus.set_property(property, &karma.to_json().to_string()[..], scope);
+    }
+
+    fn write_history(&self, dazeus: &DaZeusClient, scope: Scope, property: &str, history: &VoteHistory) {
+        if history.votes.len() == 0 {
+            dazeus.unset_property(property, scope);
+        } else {
+            dazeus.set_property(property, &history.votes.to_json().to_string()[..], scope);
+        }
+    }
+
+    fn count_vote(&self, dazeus: &DaZeusClient, scope: Scope, vote: &Vote) -> Result<KarmaValue, Box<Error>> {
+        let term = &vote.term[..];
+        let (stored, _) = try!(self.read_karma(dazeus, scope.clone(), &self.karma_property(term)[..], "karma", term));
+        let revision = stored.map(|k| k.revision).unwrap_or(0);
+
+        // only we write our tally, so no one else's vote can get lost here
+        let property = self.tally_property(term, &self.writer[..]);
+        let mut tally = match try!(self.read_karma(dazeus, scope.clone(), &property[..], "tally", term)) {
+            (Some(ref tally), _) if tally.revision == revision => tally.clone(),
+            _ => {
+                let mut tally = KarmaValue::new(term);
+                tally.revision = revision;
+                tally
+            }
+        };
+        tally.vote(vote);
+        self.write_karma(dazeus, scope.clone(), &property[..], &tally);
+
+        let karma = try!(self.get(dazeus, scope, term));
+        Ok(karma.unwrap_or(tally))
+    }
+
+    /// Add the channel of a scope to the list of channels of its network, as
+    /// DaZeus can't tell us which scopes have properties.
+    fn list_channel(&self, dazeus: &DaZeusClient, scope: &Scope) {
+        let (network, channel) = match (&scope.network, &scope.target) {
+            (&Some(ref network), &Some(ref channel)) => (network.clone(), channel.clone()),
+            _ => return,
+        };
+        if self.channels_listed.borrow().contains(&(network.clone(), channel.clone())) {
+            return;
+        }
+
+        let property = &self.channels_property[..];
+        let mut channels = read_channels(dazeus, property, &network[..]);
+        if !channels.contains(&channel) {
+            channels.push(channel.clone());
+            dazeus.set_property(property, &channels.to_json().to_string()[..], Scope::network(&network[..]));
+        }
+        self.channels_listed.borrow_mut().insert((network, channel));
+    }
+
+    fn add_to_history(&self, dazeus: &DaZeusClient, scope: Scope, vote: &Vote) -> Result<(), Box<Error>> {
+        // the id tells apart votes that are the same in every other way
+        let mut vote = vote.clone();
+        self.votes_written.set(self.votes_written.get() + 1);
+        vote.id = Some(format!("{}.{}", self.writer, self.votes_written.get()));
+
+        let property = self.votes_property(&vote.term[..], &self.writer[..]);
+        let (mut votes, _) = try!(self.read_history(dazeus, scope.clone(), &property[..], "votes", &vote.term[..]));
+        votes.add(vote);
+        self.write_history(dazeus, scope, &property[..], &votes);
+        Ok(())
+    }
+}
+
+impl KarmaStore for DaZeusStore {
+    fn get(&self, dazeus: &DaZeusClient, scope: Scope, term: &str) -> Result<Option<KarmaValue>, Box<Error>> {
+        self.read_term(dazeus, scope, term).map(|(karma, _)| karma)
+    }
+
+    fn put(&self, dazeus: &DaZeusClient, scope: Scope, karma: &KarmaValue) -> Result<(), Box<Error>> {
+        let property = self.karma_property(&karma.term[..]);
+        let (stored, _) = try!(self.read_karma(dazeus, scope.clone(), &property[..], "karma", &karma.term[..]));
+
+        // a new revision retires every tally counted on top of the old one
+        let mut karma = karma.clone();
+        karma.revision = max(karma.revision, stored.map(|k| k.revision).unwrap_or(0)) + 1;
+        self.write_karma(dazeus, scope, &property[..], &karma);
+        Ok(())
+    }
+
+    fn apply_vote(&self, dazeus: &DaZeusClient, scope: Scope, vote: &Vote) -> Result<KarmaValue, Box<Error>> {
+        let karma = try!(self.count_vote(dazeus, scope.clone(), vote));
+        self.list_channel(dazeus, &scope);
+        if let Err(e) = self.add_to_history(dazeus, scope, vote) {
+            warn!("Counted a vote on '{}', but could not add it to the history: {}", vote.term, e);
+        }
+        Ok(karma)
+    }
+
+    fn list(&self, dazeus: &DaZeusClient, scope: Scope) -> Result<Vec<KarmaValue>, Box<Error>> {
+        let mut karmas = Vec::new();
+        for term in self.stored_terms(dazeus, scope.clone()) {
+            match self.read_term(dazeus, scope.clone(), &term[..]) {
+                Ok((Some(karma), _)) => karmas.push(karma),
+                Ok((None, _)) => (),
+                Err(e) => warn!("Could not read the karma of '{}': {}", term, e),
+            }
+        }
+        Ok(karmas)
+    }
+
+    fn delete(&self, dazeus: &DaZeusClient, scope: Scope, term: &str) -> Result<bool, Box<Error>> {
+        let existed = try!(self.get(dazeus, scope.clone(), term)).is_some();
+        dazeus.unset_property(&self.karma_property(term)[..], scope.clone());
+        dazeus.unset_property(&self.history_property(term)[..], scope.clone());
+        for writer in self.writers(dazeus, scope.clone(), &self.tally_prefix[..], term) {
+            dazeus.unset_property(&self.tally_property(term, &writer[..])[..], scope.clone());
+        }
+        for writer in self.writers(dazeus, scope.clone(), &self.votes_prefix[..], term) {
+            dazeus.unset_property(&self.votes_property(term, &writer[..])[..], scope.clone());
+        }
+        Ok(existed)
+    }
+
+    fn history(&self, dazeus: &DaZeusClient, scope: Scope, term: &str) -> Result<VoteHistory, Box<Error>> {
+        self.read_term_history(dazeus, scope, term).map(|(history, _)| history)
+    }
+
+    fn put_history(&self, dazeus: &DaZeusClient, scope: Scope, history: &VoteHistory) -> Result<(), Box<Error>> {
+        let term = &history.term[..];
+        self.write_history(dazeus, scope.clone(), &self.history_property(term)[..], history);
+        for writer in self.writers(dazeus, scope.clone(), &self.votes_prefix[..], term) {
+            dazeus.unset_property(&self.votes_property(term, &writer[..])[..], scope.clone());
+        }
+        Ok(())
+    }
+
+    fn repair(&self, dazeus: &DaZeusClient, scope: Scope) -> Result<RepairReport, Box<Error>> {
+        let mut report = RepairReport { checked: 0, repaired: 0 };
+        for term in self.stored_terms(dazeus, scope.clone()) {
+            let (_, karma_repaired) = match self.read_term(dazeus, scope.clone(), &term[..]) {
+                Ok(result) => result,
+                Err(e) => {
+                    warn!("Leaving the karma of '{}' alone: {}", term, e);
+                    continue;
+                }
+            };
+            let (_, history_repaired) = try!(self.read_term_history(dazeus, scope.clone(), &term[..]));
+            report.checked += 1;
+            report.repaired += karma_repaired as usize + history_repaired as usize;
+        }
+        Ok(report)
+    }
+
+    fn get_record(&self, dazeus: &DaZeusClient, scope: Scope, record: Record, key: &str) -> Result<Option<String>, Box<Error>> {
+        let response = dazeus.get_property(&self.record_property(record, key)[..], scope);
+        Ok(response.get_str("value").map(|s| s.to_string()))
+    }
+
+    fn put_record(&self, dazeus: &DaZeusClient, scope: Scope, record: Record, key: &str, value: Option<&str>) -> Result<(), Box<Error>> {
+        let property = self.record_property(record, key);
+        match value {
+            Some(value) => dazeus.set_property(&property[..], value, scope),
+            None => dazeus.unset_property(&property[..], scope),
+        };
+        Ok(())
+    }
+}
+
+/// The channels of a network that karma was stored in with the DaZeus store.
+pub fn stored_channels(dazeus: &DaZeusClient, config: &Config, network: &str) -> Vec<String> {
+    read_channels(dazeus, &config.channels_property()[..], network)
+}
+
+fn read_channels(dazeus: &DaZeusClient, property: &str, network: &str) -> Vec<String> {
+    match dazeus.get_property(property, Scope::network(network)).get_str("value").map(json::Json::from_str) {
+        Some(Ok(json::Json::Array(items))) => items.iter().filter_map(|i| i.as_string().map(|s| s.to_string())).collect(),
+        Some(_) => {
+            warn!("Ignoring the channels stored at '{}' for '{}', it is not a list of channels", property, network);
+            Vec::new()
+        },
+        None => Vec::new(),
+    }
+}
+
+/// Keeps karma in memory only, everything is lost when the plugin stops.
+/// Handy for trying out the plugin without touching any stored karma.
+pub struct MemoryStore {
+    karma: RefCell<HashMap<(String, String), KarmaValue>>,
+    history: RefCell<HashMap<(String, String), VoteHistory>>,
+    records: RefCell<HashMap<(String, &'static str, String), String>>
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            karma: RefCell::new(HashMap::new()),
+            history: RefCell::new(HashMap::new()),
+            records: RefCell::new(HashMap::new())
+        }
+    }
+}
+
+impl KarmaStore for MemoryStore {
+    fn get(&self, _: &DaZeusClient, scope: Scope, term: &str) -> Result<Option<KarmaValue>, Box<Error>> {
+        Ok(self.karma.borrow().get(&(scope_key(&scope), normalize(term))).cloned())
+    }
+
+    fn put(&self, _: &DaZeusClient, scope: Scope, karma: &KarmaValue) -> Result<(), Box<Error>> {
+        self.karma.borrow_mut().insert((scope_key(&scope), karma.term.clone()), karma.clone());
+        Ok(())
+    }
+
+    fn apply_vote(&self, dazeus: &DaZeusClient, scope: Scope, vote: &Vote) -> Result<KarmaValue, Box<Error>> {
+        let mut karma = match try!(self.get(dazeus, scope.clone(), &vote.term[..])) {
+            Some(karma) => karma,
+            None => KarmaValue::new(&vote.term[..]),
+        };
+        karma.vote(vote);
+        try!(self.put(dazeus, scope.clone(), &karma));
+
+        let mut history = try!(self.history(dazeus, scope.clone(), &vote.term[..]));
+        history.add(vote.clone());
+        try!(self.put_history(dazeus, scope, &history));
+        Ok(karma)
+    }
+
+    fn list(&self, _: &DaZeusClient, scope: Scope) -> Result<Vec<KarmaValue>, Box<Error>> {
+        let key = scope_key(&scope);
+        Ok(self.karma.borrow().iter().filter(|&(k, _)| k.0 == key).map(|(_, v)| v.clone()).collect())
+    }
+
+    fn delete(&self, _: &DaZeusClient, scope: Scope, term: &str) -> Result<bool, Box<Error>> {
+        let key = (scope_key(&scope), normalize(term));
+        self.history.borrow_mut().remove(&key);
+        Ok(self.karma.borrow_mut().remove(&key).is_some())
+    }
+
+    fn history(&self, _: &DaZeusClient, scope: Scope, term: &str) -> Result<VoteHistory, Box<Error>> {
+        match self.history.borrow().get(&(scope_key(&scope), normalize(term))) {
+            Some(history) => Ok(history.clone()),
+            None => Ok(VoteHistory::new(term)),
+        }
+    }
+
+    fn put_history(&self, _: &DaZeusClient, scope: Scope, history: &VoteHistory) -> Result<(), Box<Error>> {
+        let key = (scope_key(&scope), history.term.clone());
+        if history.votes.len() == 0 {
+            self.history.borrow_mut().remove(&key);
+        } else {
+            self.history.borrow_mut().insert(key, history.clone());
+        }
+        Ok(())
+    }
+
+    fn repair(&self, dazeus: &DaZeusClient, scope: Scope) -> Result<RepairReport, Box<Error>> {
+        // nothing is ever parsed, so nothing can be corrupt
+        let checked = try!(self.list(dazeus, scope)).len();
+        Ok(RepairReport { checked: checked, repaired: 0 })
+    }
+
+    fn get_record(&self, _: &DaZeusClient, scope: Scope, record: Record, key: &str) -> Result<Option<String>, Box<Error>> {
+        Ok(self.records.borrow().get(&(scope_key(&scope), record.to_str(), key.to_string())).cloned())
+    }
+
+    fn put_record(&self, _: &DaZeusClient, scope: Scope, record: Record, key: &str, value: Option<&str>) -> Result<(), Box<Error>> {
+        let key = (scope_key(&scope), record.to_str(), key.to_string());
+        match value {
+            Some(value) => { self.records.borrow_mut().insert(key, value.to_string()); },
+            None => { self.records.borrow_mut().remove(&key); },
+        }
+        Ok(())
+    }
+}
+
+/// Identifies a scope as `network/channel`, either of which may be empty, for
+/// stores that don't keep their data in DaZeus.
+pub fn scope_key(scope: &Scope) -> String {
+    format!("{}/{}", scope.network.clone().unwrap_or(String::new()), scope.target.clone().unwrap_or(String::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Duration, Local};
+    use config::Config;
+    use dazeus::Scope;
+    use fake::{FakeDaZeus, CHANNEL, NETWORK};
+    use history::{Vote, MAX_VOTES};
+    use karma::{format_time, Karma, KarmaChange, KarmaOperator, KarmaStyle, KarmaValue};
+    use std::sync::Arc;
+    use std::thread;
+
+    const WRITERS: usize = 8;
+    const VOTES: usize = 50;
+
+    fn vote(voter: &str, term: &str) -> Vote {
+        Vote::new(voter, CHANNEL, &Karma {
+            term: term.to_string(),
+            change: KarmaChange::new(1, 0),
+            operator: KarmaOperator::Postfix,
+            style: KarmaStyle::Notify,
+            reason: None
+        })
+    }
+
+    #[test]
+    fn stored_times_read_back_the_same() {
+        let time = Local::now() - Duration::days(100);
+        let read = format_time(&time).parse::<DateTime<Local>>().unwrap();
+        assert_eq!(read.timestamp(), time.timestamp());
+    }
+
+    #[test]
+    fn equal_votes_are_each_added_to_the_history_once() {
+        let dazeus = FakeDaZeus::new();
+        let store = DaZeusStore::new(&Config::new());
+        let scope = Scope::network(NETWORK);
+        let vote = vote("alice", "foo");
+
+        // the same second, so only the id tells them apart
+        store.apply_vote(&dazeus, scope.clone(), &vote).unwrap();
+        let karma = store.apply_vote(&dazeus, scope.clone(), &vote).unwrap();
+        assert_eq!((karma.votes.up, karma.votes.down), (2, 0));
+        assert_eq!(store.history(&dazeus, scope, "foo").unwrap().votes.len(), 2);
+    }
+
+    #[test]
+    fn concurrent_votes_are_never_lost() {
+        let dazeus = Arc::new(FakeDaZeus::new());
+
+        // every writer is a plugin instance of its own, sharing the DaZeus core
+        let writers: Vec<_> = (0..WRITERS).map(|i| {
+            let dazeus = dazeus.clone();
+            thread::spawn(move || {
+                let store = DaZeusStore::new(&Config::new());
+                let vote = vote(&format!("voter{}", i)[..], "foo");
+                for _ in 0..VOTES {
+                    store.apply_vote(&*dazeus, Scope::network(NETWORK), &vote).unwrap();
+                }
+            })
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let store = DaZeusStore::new(&Config::new());
+        let karma = store.get(&*dazeus, Scope::network(NETWORK), "foo").unwrap().unwrap();
+        let history = store.history(&*dazeus, Scope::network(NETWORK), "foo").unwrap();
+        assert_eq!(karma.votes.up as usize, WRITERS * VOTES);
+        assert_eq!(history.votes.len(), WRITERS * VOTES);
+    }
+
+    #[test]
+    fn stored_karma_replaces_the_votes_counted_before() {
+        let dazeus = FakeDaZeus::new();
+        let (first, second) = (DaZeusStore::new(&Config::new()), DaZeusStore::new(&Config::new()));
+        let scope = Scope::network(NETWORK);
+        first.apply_vote(&dazeus, scope.clone(), &vote("alice", "foo")).unwrap();
+        second.apply_vote(&dazeus, scope.clone(), &vote("bob", "foo")).unwrap();
+
+        let mut karma = KarmaValue::new("foo");
+        karma.votes = KarmaChange::new(10, 3);
+        second.put(&dazeus, scope.clone(), &karma).unwrap();
+        assert_eq!(first.get(&dazeus, scope.clone(), "foo").unwrap().unwrap().votes, KarmaChange::new(10, 3));
+
+        let karma = first.apply_vote(&dazeus, scope.clone(), &vote("alice", "foo")).unwrap();
+        assert_eq!(karma.votes, KarmaChange::new(11, 3));
+        assert_eq!(first.list(&dazeus, scope.clone()).unwrap().len(), 1);
+
+        assert!(second.delete(&dazeus, scope.clone(), "foo").unwrap());
+        assert_eq!(first.get(&dazeus, scope.clone(), "foo").unwrap(), None);
+        assert_eq!(first.history(&dazeus, scope, "foo").unwrap().votes.len(), 0);
+    }
+
+    #[test]
+    fn only_the_latest_votes_are_remembered() {
+        let dazeus = FakeDaZeus::new();
+        let (first, second) = (DaZeusStore::new(&Config::new()), DaZeusStore::new(&Config::new()));
+        let scope = Scope::network(NETWORK);
+        for _ in 0..MAX_VOTES + 5 {
+            first.apply_vote(&dazeus, scope.clone(), &vote("alice", "foo")).unwrap();
+        }
+        for _ in 0..5 {
+            second.apply_vote(&dazeus, scope.clone(), &vote("bob", "foo")).unwrap();
+        }
+
+        let history = first.history(&dazeus, scope.clone(), "foo").unwrap();
+        assert_eq!(history.votes.len(), MAX_VOTES);
+        assert_eq!(history.voter_totals(true).iter().find(|t| t.voter == "bob").unwrap().votes.up, 5);
+        assert_eq!(first.get(&dazeus, scope, "foo").unwrap().unwrap().votes.up as usize, MAX_VOTES + 10);
+    }
+}
@@ -0,0 +1,94 @@
+use dazeus::{DaZeusClient, Event, Scope};
+use config::Config;
+use admin;
+use std::ascii::AsciiExt;
+
+/// The parts of the plugin that can be turned on and off per channel or network.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Feature {
+    /// Counting votes from chat.
+    Votes,
+    /// Replying to `[term]++` with the new karma.
+    Notify,
+    /// Replying to commands like `}karma` and `}karmatop`.
+    Commands
+}
+
+const ALL_FEATURES: [Feature; 3] = [Feature::Votes, Feature::Notify, Feature::Commands];
+
+impl Feature {
+    pub fn from_str(s: &str) -> Option<Feature> {
+        match &s.to_ascii_lowercase()[..] {
+            "votes" => Some(Feature::Votes),
+            "notify" => Some(Feature::Notify),
+            "commands" => Some(Feature::Commands),
+            _ => None,
+        }
+    }
+
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            Feature::Votes => "votes",
+            Feature::Notify => "notify",
+            Feature::Commands => "commands",
+        }
+    }
+}
+
+/// Check whether a feature is enabled in a channel. A switch set for the
+/// channel wins over one set for the whole network, which in turn wins over
+/// the configuration.
+pub fn is_enabled(dazeus: &DaZeusClient, config: &Config, network: &str, channel: &str, feature: Feature) -> bool {
+    let property = format!("{}{}", config.switch_prefix(), feature.to_str());
+    for scope in vec!(Scope::target(network, channel), Scope::network(network)) {
+        match dazeus.get_property(&property[..], scope).get_str("value") {
+            Some("on") => return true,
+            Some("off") => return false,
+            _ => (),
+        }
+    }
+
+    let disabled = match feature {
+        Feature::Votes => &config.disabled_votes,
+        Feature::Notify => &config.disabled_notify,
+        Feature::Commands => &config.disabled_commands,
+    };
+    let target = format!("{}/{}", network, channel);
+    !disabled.iter().any(|d| *d == network || d.to_ascii_lowercase() == target.to_ascii_lowercase())
+}
+
+/// Whether a `}karma` command is an attempt to turn features on or off rather
+/// than a question about the karma of `on` or `off`.
+pub fn is_switch_command(evt: &Event, dazeus: &DaZeusClient, config: &Config) -> bool {
+    evt.len() > 5 && (&evt[5] == "on" || &evt[5] == "off") && admin::is_admin(evt, dazeus, config)
+}
+
+/// Handles `}karma on|off [votes|notify|commands] [channel|network]`, which
+/// turns everything in the current channel on or off by default.
+pub fn reply_to_karma_switch_command(evt: &Event, dazeus: &DaZeusClient, config: &Config) {
+    let value = &evt[5];
+    let mut features = ALL_FEATURES.to_vec();
+    let mut network_wide = false;
+    for arg in evt.params[6..].iter() {
+        match (Feature::from_str(arg), &arg.to_ascii_lowercase()[..]) {
+            (Some(feature), _) => features = vec!(feature),
+            (None, "channel") => network_wide = false,
+            (None, "network") => network_wide = true,
+            _ => {
+                dazeus.reply(&evt, "Use `karma on|off [votes|notify|commands] [channel|network]`", true);
+                return;
+            }
+        }
+    }
+
+    let scope = if network_wide { Scope::network(&evt[0]) } else { Scope::target(&evt[0], &evt[2]) };
+    for feature in features.iter() {
+        let property = format!("{}{}", config.switch_prefix(), feature.to_str());
+        dazeus.set_property(&property[..], value, scope.clone());
+    }
+
+    let names = features.iter().map(|f| f.to_str()).collect::<Vec<&str>>().connect(", ");
+    let place = if network_wide { format!("network {}", &evt[0]) } else { evt[2].to_string() };
+    info!("'{}' turned {} {} for {} in '{}'", &evt[1], value, names, place, &evt[0]);
+    dazeus.reply(&evt, &format!("Turned {} {} for {}", value, names, place)[..], true);
+}
@@ -0,0 +1,182 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Turn a term into the form it is stored under: compatibility composed (NFKC),
+/// case folded and with all whitespace collapsed into single spaces, so that
+/// `Ärger`, `ärger` and `a\u{308}rger` all end up being the same term.
+pub fn normalize(term: &str) -> String {
+    let folded = term.nfkc().flat_map(|c| case_fold(c).into_iter()).collect::<String>();
+    let composed = folded.nfkc().collect::<String>();
+    composed.split_whitespace().collect::<Vec<&str>>().connect(" ")
+}
+
+/// Full case folding, as in the C and F mappings of Unicode's CaseFolding.txt
+/// (Unicode 14.0): lowercasing, except for the characters below whose folded
+/// form differs from their lowercase form. Generated with Python's
+/// `str.casefold`:
+///
+/// ```text
+/// [c for c in map(chr, range(0x110000)) if c.casefold() != c.lower()]
+/// ```
+fn case_fold(c: char) -> Vec<char> {
+    match c {
+        '\u{b5}' => vec!('\u{3bc}'),
+        '\u{df}' => vec!('s', 's'),
+        '\u{149}' => vec!('\u{2bc}', 'n'),
+        '\u{17f}' => vec!('s'),
+        '\u{1f0}' => vec!('j', '\u{30c}'),
+        '\u{345}' => vec!('\u{3b9}'),
+        '\u{390}' => vec!('\u{3b9}', '\u{308}', '\u{301}'),
+        '\u{3b0}' => vec!('\u{3c5}', '\u{308}', '\u{301}'),
+        '\u{3c2}' => vec!('\u{3c3}'),
+        '\u{3d0}' => vec!('\u{3b2}'),
+        '\u{3d1}' => vec!('\u{3b8}'),
+        '\u{3d5}' => vec!('\u{3c6}'),
+        '\u{3d6}' => vec!('\u{3c0}'),
+        '\u{3f0}' => vec!('\u{3ba}'),
+        '\u{3f1}' => vec!('\u{3c1}'),
+        '\u{3f5}' => vec!('\u{3b5}'),
+        '\u{587}' => vec!('\u{565}', '\u{582}'),
+        '\u{13a0}'...'\u{13f5}' => vec!(c),
+        '\u{13f8}'...'\u{13fd}' => vec!(shift(c, -0x8)),
+        '\u{1c80}' => vec!('\u{432}'),
+        '\u{1c81}' => vec!('\u{434}'),
+        '\u{1c82}' => vec!('\u{43e}'),
+        '\u{1c83}'...'\u{1c84}' => vec!(shift(c, -0x1842)),
+        '\u{1c85}' => vec!('\u{442}'),
+        '\u{1c86}' => vec!('\u{44a}'),
+        '\u{1c87}' => vec!('\u{463}'),
+        '\u{1c88}' => vec!('\u{a64b}'),
+        '\u{1e96}' => vec!('h', '\u{331}'),
+        '\u{1e97}' => vec!('t', '\u{308}'),
+        '\u{1e98}' => vec!('w', '\u{30a}'),
+        '\u{1e99}' => vec!('y', '\u{30a}'),
+        '\u{1e9a}' => vec!('a', '\u{2be}'),
+        '\u{1e9b}' => vec!('\u{1e61}'),
+        '\u{1e9e}' => vec!('s', 's'),
+        '\u{1f50}' => vec!('\u{3c5}', '\u{313}'),
+        '\u{1f52}' => vec!('\u{3c5}', '\u{313}', '\u{300}'),
+        '\u{1f54}' => vec!('\u{3c5}', '\u{313}', '\u{301}'),
+        '\u{1f56}' => vec!('\u{3c5}', '\u{313}', '\u{342}'),
+        '\u{1f80}'...'\u{1f87}' => vec!(shift(c, -0x80), '\u{3b9}'),
+        '\u{1f88}'...'\u{1f8f}' => vec!(shift(c, -0x88), '\u{3b9}'),
+        '\u{1f90}'...'\u{1f97}' => vec!(shift(c, -0x70), '\u{3b9}'),
+        '\u{1f98}'...'\u{1f9f}' => vec!(shift(c, -0x78), '\u{3b9}'),
+        '\u{1fa0}'...'\u{1fa7}' => vec!(shift(c, -0x40), '\u{3b9}'),
+        '\u{1fa8}'...'\u{1faf}' => vec!(shift(c, -0x48), '\u{3b9}'),
+        '\u{1fb2}' => vec!('\u{1f70}', '\u{3b9}'),
+        '\u{1fb3}' => vec!('\u{3b1}', '\u{3b9}'),
+        '\u{1fb4}' => vec!('\u{3ac}', '\u{3b9}'),
+        '\u{1fb6}' => vec!('\u{3b1}', '\u{342}'),
+        '\u{1fb7}' => vec!('\u{3b1}', '\u{342}', '\u{3b9}'),
+        '\u{1fbc}' => vec!('\u{3b1}', '\u{3b9}'),
+        '\u{1fbe}' => vec!('\u{3b9}'),
+        '\u{1fc2}' => vec!('\u{1f74}', '\u{3b9}'),
+        '\u{1fc3}' => vec!('\u{3b7}', '\u{3b9}'),
+        '\u{1fc4}' => vec!('\u{3ae}', '\u{3b9}'),
+        '\u{1fc6}' => vec!('\u{3b7}', '\u{342}'),
+        '\u{1fc7}' => vec!('\u{3b7}', '\u{342}', '\u{3b9}'),
+        '\u{1fcc}' => vec!('\u{3b7}', '\u{3b9}'),
+        '\u{1fd2}' => vec!('\u{3b9}', '\u{308}', '\u{300}'),
+        '\u{1fd3}' => vec!('\u{3b9}', '\u{308}', '\u{301}'),
+        '\u{1fd6}' => vec!('\u{3b9}', '\u{342}'),
+        '\u{1fd7}' => vec!('\u{3b9}', '\u{308}', '\u{342}'),
+        '\u{1fe2}' => vec!('\u{3c5}', '\u{308}', '\u{300}'),
+        '\u{1fe3}' => vec!('\u{3c5}', '\u{308}', '\u{301}'),
+        '\u{1fe4}' => vec!('\u{3c1}', '\u{313}'),
+        '\u{1fe6}' => vec!('\u{3c5}', '\u{342}'),
+        '\u{1fe7}' => vec!('\u{3c5}', '\u{308}', '\u{342}'),
+        '\u{1ff2}' => vec!('\u{1f7c}', '\u{3b9}'),
+        '\u{1ff3}' => vec!('\u{3c9}', '\u{3b9}'),
+        '\u{1ff4}' => vec!('\u{3ce}', '\u{3b9}'),
+        '\u{1ff6}' => vec!('\u{3c9}', '\u{342}'),
+        '\u{1ff7}' => vec!('\u{3c9}', '\u{342}', '\u{3b9}'),
+        '\u{1ffc}' => vec!('\u{3c9}', '\u{3b9}'),
+        '\u{ab70}'...'\u{abbf}' => vec!(shift(c, -0x97d0)),
+        '\u{fb00}' => vec!('f', 'f'),
+        '\u{fb01}' => vec!('f', 'i'),
+        '\u{fb02}' => vec!('f', 'l'),
+        '\u{fb03}' => vec!('f', 'f', 'i'),
+        '\u{fb04}' => vec!('f', 'f', 'l'),
+        '\u{fb05}' => vec!('s', 't'),
+        '\u{fb06}' => vec!('s', 't'),
+        '\u{fb13}' => vec!('\u{574}', '\u{576}'),
+        '\u{fb14}' => vec!('\u{574}', '\u{565}'),
+        '\u{fb15}' => vec!('\u{574}', '\u{56b}'),
+        '\u{fb16}' => vec!('\u{57e}', '\u{576}'),
+        '\u{fb17}' => vec!('\u{574}', '\u{56d}'),
+        _ => c.to_lowercase().collect(),
+    }
+}
+
+fn shift(c: char, offset: i32) -> char {
+    ::std::char::from_u32((c as i32 + offset) as u32).unwrap_or(c)
+}
+
+/// Characters that can be part of an implicit term (`term++`): letters and
+/// digits from any script, the underscore and emoji.
+pub fn is_term_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || is_emoji(c)
+}
+
+/// Characters that never start a term, but modify the character before them:
+/// combining marks, variation selectors and the zero width joiner used in
+/// emoji sequences.
+pub fn is_term_mark(c: char) -> bool {
+    match c {
+        '\u{300}'...'\u{36f}' | '\u{1ab0}'...'\u{1aff}' | '\u{1dc0}'...'\u{1dff}' |
+        '\u{20d0}'...'\u{20ff}' | '\u{fe20}'...'\u{fe2f}' => true,
+        '\u{200d}' | '\u{fe00}'...'\u{fe0f}' | '\u{e0020}'...'\u{e007f}' => true,
+        _ => false,
+    }
+}
+
+/// Whether a karma change that starts at `pos` (like `++term`) stands on its own,
+/// rather than being glued to the end of some other word.
+pub fn is_boundary_before(input: &str, pos: usize) -> bool {
+    match input[..pos].chars().rev().next() {
+        None => true,
+        Some(c) => c.is_whitespace() || "([,.;:".contains(c),
+    }
+}
+
+fn is_emoji(c: char) -> bool {
+    match c {
+        '\u{2600}'...'\u{27bf}' | '\u{2b00}'...'\u{2bff}' => true,
+        '\u{1f000}'...'\u{1faff}' => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize;
+
+    #[test]
+    fn equivalent_terms_are_the_same() {
+        assert_eq!(normalize("Caf\u{e9}"), "caf\u{e9}");
+        assert_eq!(normalize("cafe\u{301}"), "caf\u{e9}");
+        assert_eq!(normalize("\u{d1}AND\u{da}"), "\u{f1}and\u{fa}");
+        assert_eq!(normalize("\u{65e5}\u{672c}"), "\u{65e5}\u{672c}");
+        assert_eq!(normalize("  foo \u{3000} bar "), "foo bar");
+    }
+
+    #[test]
+    fn terms_are_case_folded() {
+        assert_eq!(normalize("STRASSE"), normalize("stra\u{df}e"));
+        assert_eq!(normalize("\u{3a3}\u{399}\u{3a3}\u{3a5}\u{3a6}\u{39f}\u{3a3}"), normalize("\u{3c3}\u{3b9}\u{3c3}\u{3c5}\u{3c6}\u{3bf}\u{3c2}"));
+        // alpha with iota subscript folds to alpha iota, as does its title case form
+        assert_eq!(normalize("\u{1fb3}"), "\u{3b1}\u{3b9}");
+        assert_eq!(normalize("\u{1fbc}"), normalize("\u{1fb3}"));
+        assert_eq!(normalize("\u{1f88}"), "\u{1f00}\u{3b9}");
+        // Cherokee folds to its uppercase letters
+        assert_eq!(normalize("\u{ab70}"), "\u{13a0}");
+        assert_eq!(normalize("\u{13f8}"), "\u{13f0}");
+        assert_eq!(normalize("\u{b5}"), "\u{3bc}");
+    }
+
+    #[test]
+    fn emoji_sequences_are_kept() {
+        let coder = "\u{1f469}\u{200d}\u{1f4bb}";
+        assert_eq!(normalize(coder), coder);
+    }
+}
@@ -0,0 +1,209 @@
+use rustc_serialize::json;
+use rustc_serialize::json::ToJson;
+use error::KarmaError;
+use chrono::{Duration, Local, NaiveDate};
+use dazeus::{DaZeusClient, Scope};
+use store::{KarmaStore, Record};
+use karma::{Karma, KarmaChange};
+use term::normalize;
+use std::ascii::AsciiExt;
+use std::collections::BTreeMap;
+
+/// How many days of votes are kept around.
+const MAX_TREND_DAYS: i64 = 366;
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrendPeriod {
+    Day,
+    Week,
+    Month,
+    Year
+}
+
+impl TrendPeriod {
+    pub fn from_str(s: &str) -> Option<TrendPeriod> {
+        match &s.to_ascii_lowercase()[..] {
+            "day" => Some(TrendPeriod::Day),
+            "week" => Some(TrendPeriod::Week),
+            "month" => Some(TrendPeriod::Month),
+            "year" => Some(TrendPeriod::Year),
+            _ => None,
+        }
+    }
+
+    pub fn days(&self) -> i64 {
+        match *self {
+            TrendPeriod::Day => 1,
+            TrendPeriod::Week => 7,
+            TrendPeriod::Month => 30,
+            TrendPeriod::Year => 365,
+        }
+    }
+
+    /// How many days each point in the graph of this period covers, so a year
+    /// is drawn week by week rather than as 365 characters.
+    pub fn days_per_point(&self) -> usize {
+        match *self {
+            TrendPeriod::Day | TrendPeriod::Week => 1,
+            TrendPeriod::Month | TrendPeriod::Year => 7,
+        }
+    }
+
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            TrendPeriod::Day => "day",
+            TrendPeriod::Week => "week",
+            TrendPeriod::Month => "month",
+            TrendPeriod::Year => "year",
+        }
+    }
+}
+
+/// Votes on a term, counted per day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trend {
+    pub term: String,
+    pub days: BTreeMap<NaiveDate, KarmaChange>
+}
+
+impl Trend {
+    pub fn new(term: &str) -> Trend {
+        Trend { term: normalize(term), days: BTreeMap::new() }
+    }
+
+    pub fn from_str(term: &str, s: &str) -> Result<Trend, Box<::std::error::Error>> {
+        let data = try!(json::Json::from_str(s));
+        let obj = match data.as_object() {
+            Some(obj) => obj,
+            None => return Err(From::from(KarmaError::new("Invalid json: trend is not an object")))
+        };
+
+        let mut trend = Trend::new(term);
+        for (day, votes) in obj.iter() {
+            let date = try!(NaiveDate::parse_from_str(&day[..], "%Y-%m-%d"));
+            let up = votes.find("up").and_then(|v| v.as_u64());
+            let down = votes.find("down").and_then(|v| v.as_u64());
+            match (up, down) {
+                (Some(up), Some(down)) => { trend.days.insert(date, KarmaChange::new(up, down)); },
+                _ => return Err(From::from(KarmaError::new(&format!("Invalid votes for day '{}'", day)[..]))),
+            }
+        }
+        Ok(trend)
+    }
+
+    /// Retrieve the trend of a term, an unknown term has no votes on any day.
+    pub fn load(store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope, term: &str) -> Result<Trend, Box<::std::error::Error>> {
+        match try!(store.get_record(dazeus, scope, Record::Trend, &normalize(term)[..])) {
+            Some(s) => Trend::from_str(term, &s[..]),
+            None => Ok(Trend::new(term)),
+        }
+    }
+
+    pub fn save(&self, store: &KarmaStore, dazeus: &DaZeusClient, scope: Scope) -> Result<(), Box<::std::error::Error>> {
+        store.put_record(dazeus, scope, Record::Trend, &self.term[..], Some(&self.to_json().to_string()[..]))
+    }
+
+    /// Count a vote towards today, forgetting about days that are too long ago.
+    pub fn vote(&mut self, karma: &Karma) {
+        let today = Local::today().naive_local();
+        self.add(today, karma.change);
+
+        let oldest = today - Duration::days(MAX_TREND_DAYS);
+        self.days = self.days.iter().filter(|&(d, _)| *d > oldest).map(|(d, v)| (*d, *v)).collect();
+    }
+
+    pub fn merge(&mut self, other: &Trend) {
+        for (day, votes) in other.days.iter() {
+            self.add(*day, *votes);
+        }
+    }
+
+    fn add(&mut self, day: NaiveDate, change: KarmaChange) {
+        let votes = self.days.entry(day).or_insert(KarmaChange::new(0, 0));
+        votes.up += change.up;
+        votes.down += change.down;
+    }
+
+    /// The net change in karma for each of the last `days` days, oldest first.
+    pub fn daily_totals(&self, days: i64) -> Vec<i64> {
+        let today = Local::today().naive_local();
+        (0..days).rev().map(|ago| {
+            match self.days.get(&(today - Duration::days(ago))) {
+                Some(votes) => votes.total(),
+                None => 0,
+            }
+        }).collect()
+    }
+}
+
+impl json::ToJson for Trend {
+    fn to_json(&self) -> json::Json {
+        let mut obj = json::Object::new();
+        for (day, votes) in self.days.iter() {
+            let mut v = json::Object::new();
+            v.insert("up".to_string(), votes.up.to_json());
+            v.insert("down".to_string(), votes.down.to_json());
+            obj.insert(day.format("%Y-%m-%d").to_string(), v.to_json());
+        }
+        obj.to_json()
+    }
+}
+
+/// Add up daily totals into totals for every `size` days. The newest bucket ends
+/// with the last day, so only the oldest one may cover fewer days.
+pub fn bucket_totals(totals: &[i64], size: usize) -> Vec<i64> {
+    let sum = |days: &[i64]| days.iter().fold(0, |a, b| a + b);
+    let oldest = totals.len() % size;
+    let mut buckets = Vec::new();
+    if oldest > 0 {
+        buckets.push(sum(&totals[..oldest]));
+    }
+    buckets.extend(totals[oldest..].chunks(size).map(sum));
+    buckets
+}
+
+/// Render how karma developed over a number of days as a small graph.
+pub fn sparkline(totals: &[i64]) -> String {
+    let mut running = 0;
+    let levels = totals.iter().map(|t| { running += *t; running }).collect::<Vec<i64>>();
+
+    let min = levels.iter().fold(0, |a, b| if *b < a { *b } else { a });
+    let max = levels.iter().fold(0, |a, b| if *b > a { *b } else { a });
+    levels.iter().map(|l| {
+        if max == min {
+            SPARKS[0]
+        } else {
+            SPARKS[((l - min) * (SPARKS.len() as i64 - 1) / (max - min)) as usize]
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn days_are_added_up_per_bucket() {
+        assert_eq!(bucket_totals(&[1, 2, 3, 4], 1), vec!(1, 2, 3, 4));
+        assert_eq!(bucket_totals(&[1, 2, 3, 4, 5], 2), vec!(1, 5, 9));
+        assert_eq!(bucket_totals(&[], 7), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn a_year_is_drawn_per_week() {
+        let totals = (0..TrendPeriod::Year.days()).map(|d| d % 3 - 1).collect::<Vec<i64>>();
+        let points = bucket_totals(&totals[..], TrendPeriod::Year.days_per_point());
+        assert_eq!(points.len(), 53);
+        assert_eq!(points.iter().fold(0, |a, b| a + b), totals.iter().fold(0, |a, b| a + b));
+        assert_eq!(sparkline(&points[..]).chars().count(), 53);
+        assert_eq!(bucket_totals(&[0; 30], TrendPeriod::Month.days_per_point()).len(), 5);
+    }
+
+    #[test]
+    fn sparklines_follow_the_running_total() {
+        assert_eq!(sparkline(&[1, 1, -2]), "\u{2584}\u{2588}\u{2581}");
+        assert_eq!(sparkline(&[0, 0]), "\u{2581}\u{2581}");
+    }
+}